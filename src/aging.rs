@@ -0,0 +1,75 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+type BaseFn<T> = dyn Fn(&T) -> f64 + Send + Sync;
+type AgedFn<T> = dyn Fn(&T, Duration) -> f64 + Send + Sync;
+
+/// Raises the effective priority of queued elements with the time they have
+/// been waiting, so low-priority elements are not starved under load.
+///
+/// An aging policy replaces the queue's comparator: elements are ordered by
+/// their effective priority, greatest first.
+pub struct Aging<T> {
+    kind: Kind<T>,
+}
+
+enum Kind<T> {
+    Linear { base: Box<BaseFn<T>>, per_second: f64 },
+    Custom { effective: Box<AgedFn<T>>, refresh: Duration },
+}
+
+impl<T> Aging<T> {
+    /// Effective priority is `base(t) + per_second * seconds_waited`.
+    ///
+    /// The relative order of two elements never changes as they age at the same
+    /// rate, so this costs nothing beyond computing `base` once per push.
+    pub fn linear<F>(base: F, per_second: f64) -> Aging<T>
+    where
+        F: Fn(&T) -> f64 + Send + Sync + 'static,
+    {
+        Aging {
+            kind: Kind::Linear { base: Box::new(base), per_second },
+        }
+    }
+
+    /// Effective priority is `effective(t, waited)`.
+    ///
+    /// Priorities are recomputed for the whole queue at most once per `refresh`,
+    /// lazily on the next pop, so ordering may lag behind by up to `refresh`.
+    pub fn custom<F>(effective: F, refresh: Duration) -> Aging<T>
+    where
+        F: Fn(&T, Duration) -> f64 + Send + Sync + 'static,
+    {
+        Aging {
+            kind: Kind::Custom { effective: Box::new(effective), refresh },
+        }
+    }
+
+    /// Sort key for `t`: greater keys pop first. `epoch` is a fixed point in
+    /// time shared by all keys of one queue.
+    pub(crate) fn key(&self, t: &T, enqueued: Instant, epoch: Instant, now: Instant) -> f64 {
+        match &self.kind {
+            Kind::Linear { base, per_second } => {
+                base(t) - per_second * enqueued.saturating_duration_since(epoch).as_secs_f64()
+            }
+            Kind::Custom { effective, .. } => effective(t, now.saturating_duration_since(enqueued)),
+        }
+    }
+
+    /// How often keys go stale and have to be recomputed, if ever.
+    pub(crate) fn refresh(&self) -> Option<Duration> {
+        match &self.kind {
+            Kind::Linear { .. } => None,
+            Kind::Custom { refresh, .. } => Some(*refresh),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Aging<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            Kind::Linear { per_second, .. } => f.debug_struct("Linear").field("per_second", per_second).finish(),
+            Kind::Custom { refresh, .. } => f.debug_struct("Custom").field("refresh", refresh).finish(),
+        }
+    }
+}
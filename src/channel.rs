@@ -0,0 +1,320 @@
+use std::error;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{PopError, PriorityBlockingQueue, PushError};
+
+/// Creates a bounded priority channel: elements sent through any sender are
+/// received greatest first by any receiver.
+///
+/// Once every sender is dropped, receivers drain what is left and then get
+/// disconnected errors. Once every receiver is dropped, sends fail and hand the
+/// element back.
+pub fn priority_channel<T: Ord>(capacity: usize) -> (PrioritySender<T>, PriorityReceiver<T>) {
+    priority_channel_from(PriorityBlockingQueue::new(capacity))
+}
+
+/// Like `priority_channel`, but over an already configured queue, e.g. one with
+/// a custom comparator.
+pub fn priority_channel_from<T>(queue: PriorityBlockingQueue<T>) -> (PrioritySender<T>, PriorityReceiver<T>) {
+    let shared = Arc::new(Shared {
+        queue,
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+    });
+    (
+        PrioritySender { shared: Arc::clone(&shared) },
+        PriorityReceiver { shared },
+    )
+}
+
+/// Both sides signal disconnection by closing the queue: receivers still drain
+/// it, while sends into a closed queue are refused.
+#[derive(Debug)]
+struct Shared<T> {
+    queue: PriorityBlockingQueue<T>,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+}
+
+#[derive(Debug)]
+pub struct PrioritySender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+#[derive(Debug)]
+pub struct PriorityReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Why an element could not be sent. Both variants hand the element back.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SendError<T> {
+    /// Every receiver is gone.
+    Disconnected(T),
+    /// The element alone outweighs the weight limit of the underlying queue,
+    /// so retrying cannot help.
+    TooHeavy(T),
+}
+
+impl<T> SendError<T> {
+    /// The element that could not be sent.
+    pub fn into_inner(self) -> T {
+        match self {
+            SendError::Disconnected(t) | SendError::TooHeavy(t) => t,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    Full(T),
+    /// The element alone outweighs the weight limit of the underlying queue,
+    /// so retrying cannot help.
+    TooHeavy(T),
+    Disconnected(T),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Disconnected,
+}
+
+// Like `PushError`, the element is left out so that the errors can be
+// propagated whatever `T` is.
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SendError::Disconnected(_) => f.write_str("Disconnected(..)"),
+            SendError::TooHeavy(_) => f.write_str("TooHeavy(..)"),
+        }
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            SendError::Disconnected(_) => "sending on a disconnected channel",
+            SendError::TooHeavy(_) => "element outweighs the channel's weight limit",
+        })
+    }
+}
+
+impl<T> error::Error for SendError<T> {}
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => f.write_str("Full(..)"),
+            TrySendError::TooHeavy(_) => f.write_str("TooHeavy(..)"),
+            TrySendError::Disconnected(_) => f.write_str("Disconnected(..)"),
+        }
+    }
+}
+
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            TrySendError::Full(_) => "channel is full",
+            TrySendError::TooHeavy(_) => "element outweighs the channel's weight limit",
+            TrySendError::Disconnected(_) => "sending on a disconnected channel",
+        })
+    }
+}
+
+impl<T> error::Error for TrySendError<T> {}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            TryRecvError::Empty => "channel is empty",
+            TryRecvError::Disconnected => "channel is disconnected and drained",
+        })
+    }
+}
+
+impl error::Error for TryRecvError {}
+
+impl<T> PrioritySender<T> {
+    /// Sends `t`, blocking until there is free capacity.
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        // `put` neither times out nor reports a full queue.
+        self.shared.queue.put(t).map_err(|e| match e {
+            PushError::TooHeavy(t) => SendError::TooHeavy(t),
+            PushError::Closed(t) | PushError::Full(t) | PushError::Timeout(t) => SendError::Disconnected(t),
+        })
+    }
+
+    pub fn try_send(&self, t: T) -> Result<(), TrySendError<T>> {
+        self.shared.queue.try_push(t).map_err(|e| match e {
+            PushError::Full(t) | PushError::Timeout(t) => TrySendError::Full(t),
+            PushError::TooHeavy(t) => TrySendError::TooHeavy(t),
+            PushError::Closed(t) => TrySendError::Disconnected(t),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shared.queue.is_empty()
+    }
+}
+
+impl<T> PriorityReceiver<T> {
+    /// Receives the greatest element, blocking while the channel is empty.
+    /// Fails with `PopError::Disconnected` once every sender is gone and the
+    /// channel is drained.
+    pub fn recv(&self) -> Result<T, PopError> {
+        self.shared.queue.pop().ok_or(PopError::Disconnected)
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let queue = &self.shared.queue;
+        let state = queue.lock();
+        let closed = state.closed;
+        match queue.pop_locked(state) {
+            Some(t) => Ok(t),
+            None if closed => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Like `recv`, but gives up with `PopError::Timeout` after `timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, PopError> {
+        self.shared.queue.pop_timeout(timeout).map_err(|e| match e {
+            PopError::Closed => PopError::Disconnected,
+            e => e,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shared.queue.is_empty()
+    }
+}
+
+impl<T> Clone for PrioritySender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        PrioritySender { shared: Arc::clone(&self.shared) }
+    }
+}
+
+impl<T> Clone for PriorityReceiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.receivers.fetch_add(1, Ordering::Relaxed);
+        PriorityReceiver { shared: Arc::clone(&self.shared) }
+    }
+}
+
+impl<T> Drop for PrioritySender<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.queue.close();
+        }
+    }
+}
+
+impl<T> Drop for PriorityReceiver<T> {
+    fn drop(&mut self) {
+        if self.shared.receivers.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.queue.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use std::error::Error;
+
+    use crate::{
+        priority_channel, priority_channel_from, PopError, PriorityBlockingQueue, SendError, TryRecvError,
+        TrySendError,
+    };
+
+    #[test]
+    fn it_should_receive_in_priority_order() {
+        let (tx, rx) = priority_channel(10);
+        tx.send(1).unwrap();
+        tx.send(3).unwrap();
+        tx.clone().send(2).unwrap();
+        assert_eq!(rx.recv(), Ok(3));
+        assert_eq!(rx.clone().try_recv(), Ok(2));
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Ok(1));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(rx.recv_timeout(Duration::from_millis(50)), Err(PopError::Timeout));
+    }
+
+    #[test]
+    fn it_should_disconnect_receivers_after_last_sender_drops_and_channel_drains() {
+        let (tx, rx) = priority_channel(10);
+        let tx_clone = tx.clone();
+        tx.send(1).unwrap();
+        drop(tx);
+        tx_clone.send(2).unwrap();
+        let receiver = thread::spawn(move || (rx.recv(), rx.recv(), rx.recv(), rx.try_recv()));
+        thread::sleep(Duration::from_millis(100));
+        drop(tx_clone);
+        assert_eq!(
+            receiver.join().unwrap(),
+            (Ok(2), Ok(1), Err(PopError::Disconnected), Err(TryRecvError::Disconnected))
+        );
+    }
+
+    #[test]
+    fn it_should_hand_element_back_after_last_receiver_drops() {
+        let (tx, rx) = priority_channel(1);
+        tx.send(1).unwrap();
+        assert_eq!(tx.try_send(2), Err(TrySendError::Full(2)));
+        let blocked = {
+            let tx = tx.clone();
+            thread::spawn(move || tx.send(3))
+        };
+        let rx_clone = rx.clone();
+        drop(rx);
+        thread::sleep(Duration::from_millis(100));
+        drop(rx_clone);
+        assert_eq!(blocked.join().unwrap(), Err(SendError::Disconnected(3)));
+        assert_eq!(tx.send(4), Err(SendError::Disconnected(4)));
+        assert_eq!(tx.try_send(5), Err(TrySendError::Disconnected(5)));
+    }
+
+    #[test]
+    fn it_should_tell_too_heavy_elements_apart_from_a_full_channel() {
+        let (tx, rx) = priority_channel_from(PriorityBlockingQueue::new(10).with_weigher(5, |t: &usize| *t));
+        assert_eq!(tx.try_send(6), Err(TrySendError::TooHeavy(6)));
+        assert_eq!(tx.send(6), Err(SendError::TooHeavy(6)));
+        tx.try_send(5).unwrap();
+        assert_eq!(tx.try_send(1), Err(TrySendError::Full(1)));
+        assert_eq!(rx.try_recv(), Ok(5));
+    }
+
+    #[test]
+    fn it_should_propagate_channel_errors_with_question_mark() {
+        struct NotDebug;
+        fn send_and_recv_twice(capacity: usize) -> Result<u8, Box<dyn Error + Send + Sync>> {
+            let (tx, rx) = priority_channel(capacity);
+            tx.try_send(1)?;
+            drop(tx);
+            Ok(rx.recv()? + rx.recv_timeout(Duration::from_millis(10))?)
+        }
+        let e = send_and_recv_twice(1).unwrap_err();
+        assert_eq!(e.downcast_ref::<PopError>(), Some(&PopError::Disconnected));
+        assert_eq!(e.to_string(), "channel is disconnected and drained");
+        assert_eq!(send_and_recv_twice(0).unwrap_err().to_string(), "channel is full");
+        let (tx, _rx) = priority_channel_from(PriorityBlockingQueue::with_key(0, |_: &NotDebug| 0));
+        let e = tx.try_send(NotDebug).unwrap_err();
+        assert_eq!(format!("{:?}: {}", e, e), "Full(..): channel is full");
+    }
+}
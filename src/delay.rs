@@ -0,0 +1,153 @@
+use std::time::{Duration, Instant};
+
+use crate::{PopError, Prioritized, PriorityBlockingQueue, PushError, Side, TieBreak};
+
+/// Delays are capped at about a century, so that the ready time can always be
+/// represented as an `Instant`.
+const MAX_DELAY: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+/// A blocking queue whose elements can only be popped once their ready time has
+/// come, earliest first. Elements with the same ready time come out in push order.
+#[derive(Debug)]
+pub struct DelayQueue<T> {
+    queue: PriorityBlockingQueue<Prioritized<T, Instant>>,
+}
+
+impl<T> DelayQueue<T> {
+    pub fn new(max_capacity: usize) -> DelayQueue<T> {
+        DelayQueue {
+            queue: PriorityBlockingQueue::new_min(max_capacity).with_tie_break(TieBreak::Fifo),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Closes the queue. Elements already queued are still handed out once
+    /// they are ready; after that `pop` returns `None`.
+    pub fn close(&self) {
+        self.queue.close()
+    }
+
+    /// Pushes `t` to become poppable at `ready_at`.
+    pub fn push(&self, t: T, ready_at: Instant) -> Result<(), PushError<T>> {
+        self.queue
+            .push_with_priority(t, ready_at)
+            .map_err(|e| e.map(|prioritized| prioritized.value))
+    }
+
+    /// Pushes `t` to become poppable after `delay`, which is capped at about a
+    /// century.
+    pub fn push_after(&self, t: T, delay: Duration) -> Result<(), PushError<T>> {
+        self.push(t, Instant::now() + delay.min(MAX_DELAY))
+    }
+
+    /// Pops the element with the earliest ready time, blocking until it is ready.
+    /// Returns `None` once the queue is closed and drained.
+    pub fn pop(&self) -> Option<T> {
+        self.pop_deadline(None).ok()
+    }
+
+    /// Pops the element with the earliest ready time if it is ready, without blocking.
+    pub fn try_pop(&self) -> Option<T> {
+        self.pop_deadline(Some(Instant::now())).ok()
+    }
+
+    /// Like `pop`, but gives up with `PopError::Timeout` if nothing becomes ready within `timeout`.
+    /// A timeout too large to represent as a deadline waits forever.
+    pub fn pop_timeout(&self, timeout: Duration) -> Result<T, PopError> {
+        self.pop_deadline(Instant::now().checked_add(timeout))
+    }
+
+    /// Waits on the queue's `non_empty` condition until the head is ready. A push
+    /// notifies it as well, so a waiter re-checks when an earlier element arrives.
+    fn pop_deadline(&self, deadline: Option<Instant>) -> Result<T, PopError> {
+        let queue = &self.queue;
+        let mut state = queue.lock();
+        loop {
+            let now = Instant::now();
+            let ready_at = match state.elements.peek().map(|head| head.priority) {
+                Some(ready_at) if ready_at <= now => {
+                    let more = state.elements.len() > 1;
+                    let popped = queue.pop_locked(state).map(|head| head.value);
+                    // Pass the baton: whoever waits next has to time its wait on the new head.
+                    if more {
+                        queue.non_empty.notify_one();
+                    }
+                    return popped.ok_or(PopError::Closed);
+                }
+                None if state.closed => return Err(PopError::Closed),
+                ready_at => ready_at,
+            };
+            let wake_at = match (ready_at, deadline) {
+                (Some(ready_at), Some(deadline)) => Some(ready_at.min(deadline)),
+                (ready_at, deadline) => ready_at.or(deadline),
+            };
+            if deadline.is_some_and(|deadline| now >= deadline) {
+                return Err(PopError::Timeout);
+            }
+            state = queue.wait_once(Side::Consumer, state, wake_at.map(|wake_at| wake_at - now));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::{DelayQueue, PopError};
+
+    #[test]
+    fn it_should_pop_elements_once_ready_in_ready_order() {
+        let q = DelayQueue::new(10);
+        let start = Instant::now();
+        q.push_after("second", Duration::from_millis(200)).unwrap();
+        q.push_after("first", Duration::from_millis(100)).unwrap();
+        assert_eq!(q.try_pop(), None);
+        assert_eq!(q.pop(), Some("first"));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(q.pop(), Some("second"));
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn it_should_wake_waiter_early_for_earlier_element() {
+        let q = Arc::new(DelayQueue::new(10));
+        q.push_after("late", Duration::from_secs(10)).unwrap();
+        let q_clone = Arc::clone(&q);
+        let start = Instant::now();
+        let consumer = thread::spawn(move || q_clone.pop());
+        thread::sleep(Duration::from_millis(100));
+        q.push_after("early", Duration::from_millis(100)).unwrap();
+        assert_eq!(consumer.join().unwrap(), Some("early"));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn it_should_time_out_when_nothing_is_ready() {
+        let q = DelayQueue::new(10);
+        q.push_after(1, Duration::from_secs(10)).unwrap();
+        assert_eq!(q.pop_timeout(Duration::from_millis(100)), Err(PopError::Timeout));
+
+        let q = DelayQueue::<i32>::new(10);
+        q.close();
+        assert_eq!(q.pop_timeout(Duration::from_millis(100)), Err(PopError::Closed));
+    }
+
+    #[test]
+    fn it_should_accept_durations_too_large_for_an_instant() {
+        let q = DelayQueue::new(10);
+        q.push_after("never", Duration::MAX).unwrap();
+        q.push_after("now", Duration::from_millis(0)).unwrap();
+        assert_eq!(q.pop_timeout(Duration::MAX), Ok("now"));
+        assert_eq!(q.try_pop(), None);
+        assert_eq!(q.len(), 1);
+    }
+}
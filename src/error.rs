@@ -0,0 +1,116 @@
+use std::error;
+use std::fmt;
+
+/// Why an element could not be pushed. Every variant hands the element back.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PushError<T> {
+    /// The queue is at capacity.
+    Full(T),
+    /// The element alone weighs more than the queue's weight limit.
+    TooHeavy(T),
+    /// No capacity freed up before the timeout.
+    Timeout(T),
+    /// The queue is closed.
+    Closed(T),
+}
+
+impl<T> PushError<T> {
+    /// The element that could not be pushed.
+    pub fn into_inner(self) -> T {
+        match self {
+            PushError::Full(t) | PushError::TooHeavy(t) | PushError::Timeout(t) | PushError::Closed(t) => t,
+        }
+    }
+
+    /// Applies `f` to the element, keeping the reason.
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> PushError<U> {
+        match self {
+            PushError::Full(t) => PushError::Full(f(t)),
+            PushError::TooHeavy(t) => PushError::TooHeavy(f(t)),
+            PushError::Timeout(t) => PushError::Timeout(f(t)),
+            PushError::Closed(t) => PushError::Closed(f(t)),
+        }
+    }
+}
+
+// Like `std::sync::mpsc::SendError`, the element is left out so that the error
+// can be propagated whatever `T` is.
+impl<T> fmt::Debug for PushError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PushError::Full(_) => f.write_str("Full(..)"),
+            PushError::TooHeavy(_) => f.write_str("TooHeavy(..)"),
+            PushError::Timeout(_) => f.write_str("Timeout(..)"),
+            PushError::Closed(_) => f.write_str("Closed(..)"),
+        }
+    }
+}
+
+impl<T> fmt::Display for PushError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            PushError::Full(_) => "queue is full",
+            PushError::TooHeavy(_) => "element outweighs the queue's weight limit",
+            PushError::Timeout(_) => "timed out waiting for free capacity",
+            PushError::Closed(_) => "queue is closed",
+        })
+    }
+}
+
+impl<T> error::Error for PushError<T> {}
+
+/// Why no element could be popped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PopError {
+    /// No element arrived before the timeout.
+    Timeout,
+    /// The queue is closed and drained.
+    Closed,
+    /// Every sender of a priority channel is gone and the channel is drained.
+    Disconnected,
+}
+
+impl fmt::Display for PopError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            PopError::Timeout => "timed out waiting for an element",
+            PopError::Closed => "queue is closed and drained",
+            PopError::Disconnected => "channel is disconnected and drained",
+        })
+    }
+}
+
+impl error::Error for PopError {}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::time::Duration;
+
+    use crate::{PopError, PriorityBlockingQueue, PushError};
+
+    struct NotDebug(u8);
+
+    #[test]
+    fn it_should_hand_back_rejected_element() {
+        let q = PriorityBlockingQueue::with_key(1, |t: &NotDebug| t.0);
+        q.push(NotDebug(1)).unwrap();
+        let e = q.push(NotDebug(2)).unwrap_err();
+        assert_eq!(format!("{:?}: {}", e, e), "Full(..): queue is full");
+        assert_eq!(e.map(|t| t.0), PushError::Full(2));
+        q.close();
+        assert_eq!(q.push(NotDebug(3)).unwrap_err().into_inner().0, 3);
+    }
+
+    #[test]
+    fn it_should_propagate_errors_with_question_mark() {
+        fn pop_two(q: &PriorityBlockingQueue<u8>) -> Result<u8, Box<dyn Error + Send + Sync>> {
+            q.push(1)?;
+            Ok(q.pop_timeout(Duration::from_millis(10))? + q.pop_timeout(Duration::from_millis(10))?)
+        }
+        let q = PriorityBlockingQueue::new(10);
+        let e = pop_two(&q).unwrap_err();
+        assert_eq!(e.to_string(), "timed out waiting for an element");
+        assert_eq!(e.downcast_ref::<PopError>(), Some(&PopError::Timeout));
+    }
+}
@@ -0,0 +1,218 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::{PriorityBlockingQueue, PushError};
+
+/// Future returned by `PriorityBlockingQueue::pop_async`.
+///
+/// An element is only taken from the queue in the `poll` that returns it, so
+/// dropping the future never loses one.
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct PopFuture<'a, T> {
+    queue: &'a PriorityBlockingQueue<T>,
+    key: Option<u64>,
+}
+
+/// Future returned by `PriorityBlockingQueue::push_async`.
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct PushFuture<'a, T> {
+    queue: &'a PriorityBlockingQueue<T>,
+    element: Option<T>,
+    key: Option<u64>,
+}
+
+impl<T> PriorityBlockingQueue<T> {
+    /// Async counterpart of `pop`: resolves to the greatest element once there
+    /// is one, or to `None` once the queue is closed and drained.
+    pub fn pop_async(&self) -> PopFuture<'_, T> {
+        PopFuture { queue: self, key: None }
+    }
+
+    /// Async counterpart of `put`: resolves once `t` has been pushed, or fails
+    /// if the queue is closed or `t` alone outweighs its limit.
+    pub fn push_async(&self, t: T) -> PushFuture<'_, T> {
+        PushFuture {
+            queue: self,
+            element: Some(t),
+            key: None,
+        }
+    }
+
+    pub(crate) fn poll_pop(&self, cx: &mut Context, key: &mut Option<u64>) -> Poll<Option<T>> {
+        let mut state = self.lock();
+        if !state.elements.is_empty() || state.closed {
+            if let Some(key) = key.take() {
+                state.pop_wakers.remove(key);
+            }
+            return Poll::Ready(self.pop_locked(state));
+        }
+        state.pop_wakers.register(key, cx.waker());
+        Poll::Pending
+    }
+
+    /// Drops the registration under `key`. If it had already been woken for an
+    /// element that it will now never take, the wakeup goes to the next waiter.
+    pub(crate) fn cancel_pop(&self, key: &mut Option<u64>) {
+        if let Some(key) = key.take() {
+            let mut state = self.lock();
+            if !state.pop_wakers.remove(key) && !state.elements.is_empty() {
+                self.notify_waiters_for_push(state, 1);
+            }
+        }
+    }
+
+    pub(crate) fn poll_push(
+        &self,
+        cx: &mut Context,
+        element: &mut Option<T>,
+        key: &mut Option<u64>,
+    ) -> Poll<Result<(), PushError<T>>> {
+        let mut state = self.lock();
+        let t = element.take().expect("push polled after completion");
+        let admitted = match self.admit(&mut state, t) {
+            Ok((t, weight)) if !self.has_room(&state, weight) => {
+                *element = Some(t);
+                state.push_wakers.register(key, cx.waker());
+                return Poll::Pending;
+            }
+            admitted => admitted,
+        };
+        if let Some(key) = key.take() {
+            state.push_wakers.remove(key);
+        }
+        Poll::Ready(admitted.map(|(t, _)| {
+            self.push_locked(state, t, false);
+        }))
+    }
+
+    /// Like `cancel_pop`, for the producer side.
+    pub(crate) fn cancel_push(&self, key: &mut Option<u64>) {
+        if let Some(key) = key.take() {
+            let mut state = self.lock();
+            if !state.push_wakers.remove(key) && self.has_room(&state, 0) {
+                self.notify_waiters_for_pop(state, 1);
+            }
+        }
+    }
+}
+
+impl<T> Future for PopFuture<'_, T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        let this = self.get_mut();
+        this.queue.poll_pop(cx, &mut this.key)
+    }
+}
+
+impl<T> Drop for PopFuture<'_, T> {
+    fn drop(&mut self) {
+        self.queue.cancel_pop(&mut self.key);
+    }
+}
+
+// The element is only ever moved out, never pinned.
+impl<T> Unpin for PushFuture<'_, T> {}
+
+impl<T> Future for PushFuture<'_, T> {
+    type Output = Result<(), PushError<T>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), PushError<T>>> {
+        let this = self.get_mut();
+        this.queue.poll_push(cx, &mut this.element, &mut this.key)
+    }
+}
+
+impl<T> Drop for PushFuture<'_, T> {
+    fn drop(&mut self) {
+        self.queue.cancel_push(&mut self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::waker::ThreadWaker;
+    use crate::{PriorityBlockingQueue, PushError};
+
+    /// Minimal executor: polls `future` on the current thread, parking in between.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let waker = ThreadWaker::current();
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn it_should_pop_async_element_pushed_by_blocking_producer() {
+        let q = Arc::new(PriorityBlockingQueue::new(10));
+        let q_clone = Arc::clone(&q);
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            q_clone.push(1).unwrap();
+        });
+        assert_eq!(block_on(q.pop_async()), Some(1));
+        q.close();
+        assert_eq!(block_on(q.pop_async()), None);
+    }
+
+    #[test]
+    fn it_should_push_async_once_blocking_consumer_frees_capacity() {
+        let q = Arc::new(PriorityBlockingQueue::new(1));
+        block_on(q.push_async(1)).unwrap();
+        let q_clone = Arc::clone(&q);
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            assert_eq!(q_clone.pop(), Some(1));
+        });
+        assert_eq!(block_on(q.push_async(2)), Ok(()));
+        assert_eq!(q.pop(), Some(2));
+        q.close();
+        assert_eq!(block_on(q.push_async(3)), Err(PushError::Closed(3)));
+    }
+
+    #[test]
+    fn it_should_pass_wakeup_on_when_woken_future_is_dropped() {
+        let q = PriorityBlockingQueue::new(10);
+        let first = Arc::new(CountingWaker::default());
+        let second = Arc::new(CountingWaker::default());
+        let mut first_pop = q.pop_async();
+        let mut second_pop = q.pop_async();
+        let first_waker = Waker::from(Arc::clone(&first));
+        let second_waker = Waker::from(Arc::clone(&second));
+        assert_eq!(Pin::new(&mut first_pop).poll(&mut Context::from_waker(&first_waker)), Poll::Pending);
+        assert_eq!(Pin::new(&mut second_pop).poll(&mut Context::from_waker(&second_waker)), Poll::Pending);
+
+        q.push(1).unwrap();
+        assert_eq!(first.0.load(Ordering::SeqCst), 1);
+        assert_eq!(second.0.load(Ordering::SeqCst), 0);
+
+        drop(first_pop);
+        assert_eq!(second.0.load(Ordering::SeqCst), 1);
+        assert_eq!(Pin::new(&mut second_pop).poll(&mut Context::from_waker(&second_waker)), Poll::Ready(Some(1)));
+    }
+}
@@ -0,0 +1,682 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Instant;
+
+use crate::aging::Aging;
+
+type CompareFn<T> = dyn Fn(&T, &T) -> Ordering + Send + Sync;
+type WeighFn<T> = dyn Fn(&T) -> usize + Send + Sync;
+
+/// Ordering used by the heap: the greatest element according to it is popped first.
+pub(crate) enum Compare<T> {
+    Fn(fn(&T, &T) -> Ordering),
+    Closure(Box<CompareFn<T>>),
+}
+
+impl<T> Compare<T> {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        match self {
+            Compare::Fn(f) => f(a, b),
+            Compare::Closure(f) => f(a, b),
+        }
+    }
+}
+
+/// How elements that compare equal are ordered relative to each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TieBreak {
+    /// No guarantee, like `std::collections::BinaryHeap`.
+    Arbitrary,
+    /// Equal elements are popped in the order they were pushed.
+    Fifo,
+    /// Equal elements are popped in reverse push order.
+    Lifo,
+}
+
+struct Entry<T> {
+    value: T,
+    seq: u64,
+    tracked: bool,
+    enqueued: Instant,
+    key: f64,
+    weight: usize,
+}
+
+/// Min-max heap over a `Vec`, ordered by a runtime comparator rather than `T: Ord`.
+/// Levels alternate between ordering their subtrees greatest and lowest first, so
+/// the greatest element is the root and the lowest one of its children: both pop
+/// and eviction take O(log n).
+///
+/// Every entry is tagged with an insertion sequence number to break ties, which
+/// `TieBreak::Arbitrary` breaks like `Fifo` so that the lowest element is
+/// always well defined. Entries
+/// pushed with `push_tracked` can be found again by that number, which is why all
+/// moves go through `swap`: it keeps `positions` in sync.
+///
+/// With an aging policy, entries are ordered by a cached `key` instead of the
+/// comparator; keys that go stale over time are recomputed on `pop` once the
+/// policy's refresh interval has passed.
+///
+/// With a weigher, every entry remembers its weight and the heap keeps their sum.
+///
+/// User code (comparator, aging, weigher, update closures) may panic midway
+/// through an operation. Entries are only ever moved by `swap`, so the heap stays
+/// structurally sound, but order may be broken: `dirty` stays set in that case
+/// and `repair` restores it.
+pub(crate) struct Heap<T> {
+    items: Vec<Entry<T>>,
+    positions: HashMap<u64, usize>,
+    compare: Compare<T>,
+    tie_break: TieBreak,
+    next_seq: u64,
+    aging: Option<Aging<T>>,
+    epoch: Instant,
+    refreshed: Instant,
+    weigher: Option<Box<WeighFn<T>>>,
+    weight: usize,
+    dirty: bool,
+}
+
+impl<T> Heap<T> {
+    pub(crate) fn with_capacity(capacity: usize, compare: Compare<T>) -> Heap<T> {
+        Heap {
+            items: Vec::with_capacity(capacity),
+            positions: HashMap::new(),
+            compare,
+            tie_break: TieBreak::Arbitrary,
+            next_seq: 0,
+            aging: None,
+            epoch: Instant::now(),
+            refreshed: Instant::now(),
+            weigher: None,
+            weight: 0,
+            dirty: false,
+        }
+    }
+
+    /// Only meant to be called while the heap is empty.
+    pub(crate) fn set_aging(&mut self, aging: Aging<T>) {
+        self.aging = Some(aging);
+    }
+
+    /// Only meant to be called while the heap is empty.
+    pub(crate) fn set_tie_break(&mut self, tie_break: TieBreak) {
+        self.tie_break = tie_break;
+    }
+
+    /// Only meant to be called while the heap is empty.
+    pub(crate) fn set_weigher(&mut self, weigher: Box<WeighFn<T>>) {
+        self.weigher = Some(weigher);
+    }
+
+    pub(crate) fn is_weighed(&self) -> bool {
+        self.weigher.is_some()
+    }
+
+    /// Weight of `t` according to the weigher, or 0 without one.
+    pub(crate) fn weigh(&self, t: &T) -> usize {
+        self.weigher.as_ref().map_or(0, |weigher| weigher(t))
+    }
+
+    /// Total weight of all entries.
+    pub(crate) fn weight(&self) -> usize {
+        self.weight
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub(crate) fn push(&mut self, t: T) {
+        self.push_entry(t, false);
+    }
+
+    /// Pushes `t` and returns the id it can later be looked up by.
+    pub(crate) fn push_tracked(&mut self, t: T) -> u64 {
+        self.push_entry(t, true)
+    }
+
+    pub(crate) fn push_entry(&mut self, t: T, tracked: bool) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let enqueued = Instant::now();
+        let key = self.key(&t, enqueued, enqueued);
+        let weight = self.weigh(&t);
+        self.insert(Entry { value: t, seq, tracked, enqueued, key, weight });
+        seq
+    }
+
+    /// Inserts `entry` as is, so that entries taken out can be put back unchanged.
+    fn insert(&mut self, entry: Entry<T>) {
+        let pos = self.items.len();
+        self.weight += entry.weight;
+        if entry.tracked {
+            self.positions.insert(entry.seq, pos);
+        }
+        self.items.push(entry);
+        self.sift_up(pos);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn pop(&mut self) -> Option<T> {
+        self.pop_timed().map(|(value, _)| value)
+    }
+
+    /// Like `pop`, but also returns when the element was pushed.
+    pub(crate) fn pop_timed(&mut self) -> Option<(T, Instant)> {
+        self.refresh_keys_if_stale();
+        self.pop_head()
+    }
+
+    /// Pops whatever `head` returns, without refreshing stale aging keys first.
+    pub(crate) fn pop_head(&mut self) -> Option<(T, Instant)> {
+        if self.items.is_empty() {
+            return None;
+        }
+        let entry = self.remove_at(0);
+        Some((entry.value, entry.enqueued))
+    }
+
+    pub(crate) fn peek(&mut self) -> Option<&T> {
+        self.refresh_keys_if_stale();
+        self.head()
+    }
+
+    /// Like `peek`, but without refreshing stale aging keys first.
+    pub(crate) fn head(&self) -> Option<&T> {
+        self.items.first().map(|entry| &entry.value)
+    }
+
+    /// Compares the heads of `a` and `b`, which must both be non-empty, by this
+    /// heap's ordering. Aging keys are recomputed against this heap's epoch, as
+    /// every heap has its own.
+    pub(crate) fn compare_heads(&self, a: &Heap<T>, b: &Heap<T>) -> Ordering {
+        let (a, b) = (&a.items[0], &b.items[0]);
+        match &self.aging {
+            Some(aging) => {
+                let now = Instant::now();
+                let key = |entry: &Entry<T>| aging.key(&entry.value, entry.enqueued, self.epoch, now);
+                key(a).total_cmp(&key(b))
+            }
+            None => self.compare.compare(&a.value, &b.value),
+        }
+    }
+
+    /// Mutable access to the head. Call `fix_head` after changing it.
+    pub(crate) fn head_mut(&mut self) -> Option<&mut T> {
+        self.dirty = true;
+        self.items.first_mut().map(|entry| &mut entry.value)
+    }
+
+    /// Restores heap order after the head was changed through `head_mut`.
+    pub(crate) fn fix_head(&mut self) {
+        if !self.items.is_empty() {
+            self.refresh_key(0, Instant::now());
+            self.reweigh(0);
+            self.sift_down(0);
+        }
+        self.dirty = false;
+    }
+
+    /// Whether a panic interrupted an operation that was restoring heap order.
+    pub(crate) fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Rebuilds the heap from its entries in linear time.
+    pub(crate) fn repair(&mut self) {
+        self.dirty = true;
+        self.weight = self.items.iter().map(|entry| entry.weight).sum();
+        self.positions.clear();
+        for (pos, entry) in self.items.iter().enumerate() {
+            if entry.tracked {
+                self.positions.insert(entry.seq, pos);
+            }
+        }
+        self.heapify();
+        self.dirty = false;
+    }
+
+    /// Removes every element, in no particular order.
+    pub(crate) fn drain(&mut self) -> Vec<T> {
+        self.positions.clear();
+        self.weight = 0;
+        self.items.drain(..).map(|entry| entry.value).collect()
+    }
+
+    /// Removes every element, greatest first.
+    pub(crate) fn drain_sorted(&mut self) -> Vec<T> {
+        self.pop_many(self.items.len()).into_iter().map(|(value, _)| value).collect()
+    }
+
+    /// Pops up to `n` elements, greatest first, along with when they were
+    /// pushed. A panic part way puts the ones already popped back.
+    pub(crate) fn pop_many(&mut self, n: usize) -> Vec<(T, Instant)> {
+        self.refresh_keys_if_stale();
+        let mut popped = Vec::with_capacity(n.min(self.items.len()));
+        self.restoring(&mut popped, |heap, popped| {
+            while popped.len() < n && !heap.items.is_empty() {
+                popped.push(heap.remove_at(0));
+            }
+        });
+        popped.into_iter().map(|entry| (entry.value, entry.enqueued)).collect()
+    }
+
+    /// Removes every element `remove` returns `true` for and rebuilds the heap
+    /// from the rest in linear time. `remove` sees every element before any is
+    /// removed, so a panic in it leaves the heap as it was.
+    pub(crate) fn remove_where<F: FnMut(&T) -> bool>(&mut self, mut remove: F) -> Vec<T> {
+        let doomed: Vec<_> = self.items.iter().map(|entry| remove(&entry.value)).collect();
+        if !doomed.contains(&true) {
+            return Vec::new();
+        }
+        let mut doomed = doomed.into_iter();
+        let (removed, kept): (Vec<_>, Vec<_>) = self.items.drain(..).partition(|_| doomed.next() == Some(true));
+        self.items = kept;
+        self.repair();
+        removed.into_iter().map(|entry| entry.value).collect()
+    }
+
+    /// References to every element, greatest first.
+    pub(crate) fn sorted(&mut self) -> Vec<&T> {
+        self.refresh_keys_if_stale();
+        let mut order: Vec<_> = (0..self.items.len()).collect();
+        order.sort_by(|&a, &b| self.ordering(b, a));
+        let items = &self.items;
+        order.into_iter().map(|pos| &items[pos].value).collect()
+    }
+
+    pub(crate) fn contains(&self, id: u64) -> bool {
+        self.positions.contains_key(&id)
+    }
+
+    pub(crate) fn remove(&mut self, id: u64) -> Option<T> {
+        let pos = *self.positions.get(&id)?;
+        Some(self.remove_at(pos).value)
+    }
+
+    /// Applies `f` to the tracked entry `id` and restores heap order around it.
+    pub(crate) fn update<F: FnOnce(&mut T)>(&mut self, id: u64, f: F) -> bool {
+        match self.positions.get(&id) {
+            Some(&pos) => {
+                self.dirty = true;
+                f(&mut self.items[pos].value);
+                self.refresh_key(pos, Instant::now());
+                self.reweigh(pos);
+                self.resift(pos);
+                self.dirty = false;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes lowest-ranked elements until `fits(len, weight)` holds for the
+    /// rest. Returns them lowest first.
+    pub(crate) fn evict_until<F: Fn(usize, usize) -> bool>(&mut self, fits: F) -> Vec<T> {
+        let mut evicted = Vec::new();
+        self.restoring(&mut evicted, |heap, evicted| {
+            while !fits(heap.items.len(), heap.weight) {
+                match heap.lowest() {
+                    Some(pos) => evicted.push(heap.remove_at(pos)),
+                    None => break,
+                }
+            }
+        });
+        evicted.into_iter().map(|entry| entry.value).collect()
+    }
+
+    /// Pushes `t`, then removes lowest-ranked elements until `fits(len, weight)`
+    /// holds, but only if all of them rank below `t`. Otherwise `t` itself is
+    /// the only element removed. Returns `t`'s id and the removed elements.
+    pub(crate) fn push_evicting_lower<F>(&mut self, t: T, tracked: bool, fits: F) -> (u64, Vec<T>)
+    where
+        F: Fn(usize, usize) -> bool,
+    {
+        let id = self.push_entry(t, tracked);
+        let mut evicted = Vec::new();
+        let outranked = self.restoring(&mut evicted, |heap, evicted| {
+            while !fits(heap.items.len(), heap.weight) {
+                let pos = heap.lowest().expect("heap holds at least the new entry");
+                let entry = heap.remove_at(pos);
+                if entry.seq == id {
+                    while let Some(entry) = evicted.pop() {
+                        heap.insert(entry);
+                    }
+                    return Some(entry);
+                }
+                evicted.push(entry);
+            }
+            None
+        });
+        match outranked {
+            Some(entry) => (id, vec![entry.value]),
+            None => (id, evicted.into_iter().map(|entry| entry.value).collect()),
+        }
+    }
+
+    /// Position of the lowest-ranked element: the root if it is alone, otherwise
+    /// the lesser of its children, which head the min levels.
+    fn lowest(&self) -> Option<usize> {
+        match self.items.len() {
+            0 => None,
+            1 => Some(0),
+            2 => Some(1),
+            _ => Some(if self.greater(1, 2) { 2 } else { 1 }),
+        }
+    }
+
+    fn remove_at(&mut self, pos: usize) -> Entry<T> {
+        // The last entry is out of place from the swap until `resift` is done.
+        self.dirty = true;
+        let last = self.items.len() - 1;
+        self.swap(pos, last);
+        let entry = self.items.pop().expect("heap is non-empty");
+        self.weight -= entry.weight;
+        if entry.tracked {
+            self.positions.remove(&entry.seq);
+        }
+        if pos < self.items.len() {
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| self.resift(pos))) {
+                self.put_back(entry);
+                panic::resume_unwind(payload);
+            }
+        }
+        self.dirty = false;
+        entry
+    }
+
+    /// Runs `f`, which moves entries it takes out of the heap into `taken`.
+    /// Should it panic, whatever is in `taken` is put back before the panic
+    /// carries on, so that no element is lost.
+    fn restoring<R, F>(&mut self, taken: &mut Vec<Entry<T>>, f: F) -> R
+    where
+        F: FnOnce(&mut Self, &mut Vec<Entry<T>>) -> R,
+    {
+        match panic::catch_unwind(AssertUnwindSafe(|| f(self, taken))) {
+            Ok(result) => result,
+            Err(payload) => {
+                taken.drain(..).for_each(|entry| self.put_back(entry));
+                panic::resume_unwind(payload)
+            }
+        }
+    }
+
+    /// Appends `entry` without restoring heap order, which is left to `repair`.
+    /// Runs no user code, so it is safe while unwinding.
+    fn put_back(&mut self, entry: Entry<T>) {
+        self.dirty = true;
+        self.weight += entry.weight;
+        if entry.tracked {
+            self.positions.insert(entry.seq, self.items.len());
+        }
+        self.items.push(entry);
+    }
+
+    fn key(&self, t: &T, enqueued: Instant, now: Instant) -> f64 {
+        match &self.aging {
+            Some(aging) => aging.key(t, enqueued, self.epoch, now),
+            None => 0.0,
+        }
+    }
+
+    fn refresh_key(&mut self, pos: usize, now: Instant) {
+        let entry = &self.items[pos];
+        let key = self.key(&entry.value, entry.enqueued, now);
+        self.items[pos].key = key;
+    }
+
+    fn reweigh(&mut self, pos: usize) {
+        let weight = self.weigh(&self.items[pos].value);
+        self.weight = self.weight - self.items[pos].weight + weight;
+        self.items[pos].weight = weight;
+    }
+
+    fn refresh_keys_if_stale(&mut self) {
+        let refresh = match self.aging.as_ref().and_then(Aging::refresh) {
+            Some(refresh) => refresh,
+            None => return,
+        };
+        let now = Instant::now();
+        if now.saturating_duration_since(self.refreshed) < refresh {
+            return;
+        }
+        self.refreshed = now;
+        self.dirty = true;
+        for pos in 0..self.items.len() {
+            self.refresh_key(pos, now);
+        }
+        self.heapify();
+        self.dirty = false;
+    }
+
+    fn heapify(&mut self) {
+        for pos in (0..self.items.len() / 2).rev() {
+            self.sift_down(pos);
+        }
+    }
+
+    /// Restores order around an entry that changed or moved to `pos`. Sifting
+    /// down only fixes its subtree; if the entry now outranks an ancestor, it is
+    /// the most extreme one of that subtree and sifting up from where it landed
+    /// fixes the rest.
+    fn resift(&mut self, pos: usize) {
+        let pos = self.sift_down(pos);
+        self.sift_up(pos);
+    }
+
+    fn swap(&mut self, a: usize, b: usize) {
+        self.items.swap(a, b);
+        for &pos in [a, b].iter() {
+            let entry = &self.items[pos];
+            if entry.tracked {
+                self.positions.insert(entry.seq, pos);
+            }
+        }
+    }
+
+    fn greater(&self, a: usize, b: usize) -> bool {
+        self.ordering(a, b) == Ordering::Greater
+    }
+
+    /// Whether `a` belongs above `b` on a max level, or on a min level if `max`
+    /// is `false`.
+    fn beats(&self, a: usize, b: usize, max: bool) -> bool {
+        if max {
+            self.greater(a, b)
+        } else {
+            self.greater(b, a)
+        }
+    }
+
+    fn ordering(&self, a: usize, b: usize) -> Ordering {
+        let (a, b) = (&self.items[a], &self.items[b]);
+        let primary = match self.aging {
+            Some(_) => a.key.total_cmp(&b.key),
+            None => self.compare.compare(&a.value, &b.value),
+        };
+        primary.then_with(|| match self.tie_break {
+            TieBreak::Arbitrary | TieBreak::Fifo => b.seq.cmp(&a.seq),
+            TieBreak::Lifo => a.seq.cmp(&b.seq),
+        })
+    }
+
+    /// Moves the entry at `pos` up past the ancestors it outranks: first its
+    /// parent, which is on the opposite kind of level, then its grandparents.
+    fn sift_up(&mut self, mut pos: usize) {
+        self.dirty = true;
+        let mut max = on_max_level(pos);
+        if pos > 0 && self.beats(pos, (pos - 1) / 2, !max) {
+            self.swap(pos, (pos - 1) / 2);
+            pos = (pos - 1) / 2;
+            max = !max;
+        }
+        while pos > 2 {
+            let grandparent = ((pos - 1) / 2 - 1) / 2;
+            if !self.beats(pos, grandparent, max) {
+                break;
+            }
+            self.swap(pos, grandparent);
+            pos = grandparent;
+        }
+        self.dirty = false;
+    }
+
+    /// Restores order in the subtree at `pos`, assuming only the entry at `pos`
+    /// is out of place. Returns where that entry ends up.
+    fn sift_down(&mut self, mut pos: usize) -> usize {
+        self.dirty = true;
+        let max = on_max_level(pos);
+        let mut landed = None;
+        while let Some(next) = self.most_extreme_descendant(pos, max) {
+            if !self.beats(next, pos, max) {
+                break;
+            }
+            self.swap(pos, next);
+            if next <= 2 * pos + 2 {
+                pos = next;
+                break;
+            }
+            let parent = (next - 1) / 2;
+            if self.beats(parent, next, max) {
+                self.swap(next, parent);
+                landed.get_or_insert(parent);
+            }
+            pos = next;
+        }
+        self.dirty = false;
+        landed.unwrap_or(pos)
+    }
+
+    /// Of the children and grandchildren of `pos`, the greatest if `max`,
+    /// otherwise the lowest.
+    fn most_extreme_descendant(&self, pos: usize, max: bool) -> Option<usize> {
+        let len = self.items.len();
+        let children = (2 * pos + 1).min(len)..(2 * pos + 3).min(len);
+        let grandchildren = (4 * pos + 3).min(len)..(4 * pos + 7).min(len);
+        children
+            .chain(grandchildren)
+            .reduce(|best, next| if self.beats(next, best, max) { next } else { best })
+    }
+}
+
+/// Whether `pos` is on a max level: the root's, and every second one below it.
+fn on_max_level(pos: usize) -> bool {
+    (usize::BITS - (pos + 1).leading_zeros()) % 2 == 1
+}
+
+impl<T: fmt::Debug> fmt::Debug for Heap<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.items.iter().map(|entry| &entry.value)).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::heap::{Compare, Heap, TieBreak};
+
+    #[test]
+    fn it_should_pop_in_comparator_order() {
+        let mut heap = Heap::with_capacity(0, Compare::Fn(|a: &i32, b: &i32| b.cmp(a)));
+        for i in [5, 1, 4, 2, 3, 2].iter() {
+            heap.push(*i);
+        }
+        let popped: Vec<_> = std::iter::from_fn(|| heap.pop()).collect();
+        assert_eq!(popped, vec![1, 2, 2, 3, 4, 5]);
+        assert!(heap.is_empty());
+    }
+
+    #[test]
+    fn it_should_remove_and_update_tracked_entries() {
+        let mut heap = Heap::with_capacity(0, Compare::Fn(i32::cmp));
+        let ids: Vec<_> = (0..100).map(|i| heap.push_tracked(i)).collect();
+        for i in 100..200 {
+            heap.push(i);
+        }
+        for id in ids.iter().step_by(2) {
+            assert!(heap.remove(*id).is_some());
+            assert!(!heap.contains(*id));
+        }
+        assert!(heap.update(ids[1], |value| *value = 1000));
+        assert!(!heap.update(ids[0], |value| *value = 2000));
+        assert_eq!(heap.pop(), Some(1000));
+        let popped: Vec<_> = std::iter::from_fn(|| heap.pop()).collect();
+        let expected: Vec<_> = (100..200).rev().chain((3..100).rev().step_by(2)).collect();
+        assert_eq!(popped, expected);
+        assert!(ids.iter().all(|id| !heap.contains(*id)));
+    }
+
+    #[test]
+    fn it_should_break_ties_by_insertion_order() {
+        let by_priority = |a: &(u8, usize), b: &(u8, usize)| a.0.cmp(&b.0);
+        let mut fifo = Heap::with_capacity(0, Compare::Fn(by_priority));
+        fifo.set_tie_break(TieBreak::Fifo);
+        let mut lifo = Heap::with_capacity(0, Compare::Fn(by_priority));
+        lifo.set_tie_break(TieBreak::Lifo);
+        for i in 0..10_000 {
+            fifo.push(((i % 4) as u8, i));
+            lifo.push(((i % 4) as u8, i));
+        }
+        let fifo: Vec<_> = std::iter::from_fn(|| fifo.pop()).collect();
+        let lifo: Vec<_> = std::iter::from_fn(|| lifo.pop()).collect();
+        for window in fifo.windows(2) {
+            assert!(window[0].0 > window[1].0 || (window[0].0 == window[1].0 && window[0].1 < window[1].1));
+        }
+        for window in lifo.windows(2) {
+            assert!(window[0].0 > window[1].0 || (window[0].0 == window[1].0 && window[0].1 > window[1].1));
+        }
+    }
+
+    #[test]
+    fn it_should_keep_greatest_and_lowest_at_the_top() {
+        let mut heap = Heap::with_capacity(0, Compare::Fn(u32::cmp));
+        let mut model = Vec::new();
+        let mut ids = Vec::new();
+        let mut seed = 17u32;
+        for _ in 0..2_000 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            let value = seed >> 16;
+            match value % 6 {
+                0 if !model.is_empty() => {
+                    let evicted = heap.evict_until(|len, _| len < model.len());
+                    let lowest = model.remove(0);
+                    assert_eq!(evicted, vec![lowest]);
+                }
+                1 if !ids.is_empty() => {
+                    let (id, _) = ids.swap_remove(value as usize % ids.len());
+                    if let Some(removed) = heap.remove(id) {
+                        let pos = model.binary_search(&removed).unwrap();
+                        model.remove(pos);
+                    }
+                }
+                2 if !ids.is_empty() => {
+                    let index = value as usize % ids.len();
+                    let (id, old) = ids[index];
+                    let new = value / 2;
+                    if heap.update(id, |value| *value = new) {
+                        let pos = model.binary_search(&old).unwrap();
+                        model.remove(pos);
+                        let pos = model.binary_search(&new).unwrap_or_else(|pos| pos);
+                        model.insert(pos, new);
+                        ids[index].1 = new;
+                    }
+                }
+                3 => assert_eq!(heap.pop(), model.pop()),
+                _ => {
+                    ids.push((heap.push_tracked(value), value));
+                    let pos = model.binary_search(&value).unwrap_or_else(|pos| pos);
+                    model.insert(pos, value);
+                }
+            }
+            assert_eq!(heap.head(), model.last());
+        }
+        let popped: Vec<_> = std::iter::from_fn(|| heap.pop()).collect();
+        assert_eq!(popped, model.into_iter().rev().collect::<Vec<_>>());
+    }
+}
@@ -1,68 +1,733 @@
-use std::collections::BinaryHeap;
-use std::sync::{Mutex, Condvar, RwLock};
+mod aging;
+mod channel;
+mod delay;
+mod error;
+mod future;
+mod heap;
+mod overflow;
+mod peek;
+mod prioritized;
+#[cfg(feature = "prometheus")]
+mod prometheus;
+mod select;
+mod stats;
+#[cfg(feature = "stream")]
+mod stream;
+mod sync;
+mod trace;
+mod waker;
+
+use std::cmp::Ordering;
+use std::task::Waker;
+use std::time::{Duration, Instant};
+
+use crate::heap::{Compare, Heap};
+use crate::overflow::OnEvict;
+use crate::sync::{Condvar, Mutex, MutexGuard};
+use crate::trace::event;
+use crate::waker::WakerSet;
+
+pub use crate::aging::Aging;
+pub use crate::channel::{
+    priority_channel, priority_channel_from, PriorityReceiver, PrioritySender, SendError, TryRecvError, TrySendError,
+};
+pub use crate::delay::DelayQueue;
+pub use crate::error::{PopError, PushError};
+pub use crate::future::{PopFuture, PushFuture};
+pub use crate::heap::TieBreak;
+pub use crate::overflow::Overflow;
+pub use crate::peek::PeekMut;
+pub use crate::prioritized::Prioritized;
+#[cfg(feature = "prometheus")]
+pub use crate::prometheus::encode_prometheus;
+pub use crate::select::Selector;
+pub use crate::stats::{Histogram, Stats};
+#[cfg(feature = "stream")]
+pub use crate::stream::{PopStream, PushSink};
 
 #[derive(Debug)]
 pub struct PriorityBlockingQueue<T> {
-    elements: RwLock<BinaryHeap<T>>,
-    non_empty: (Mutex<bool>, Condvar),
+    state: Mutex<State<T>>,
+    non_empty: Condvar,
+    not_full: Condvar,
+    max_weight: usize,
+    overflow: Overflow,
+    on_evict: Option<OnEvict<T>>,
+}
+
+#[derive(Debug)]
+struct State<T> {
+    elements: Heap<T>,
     max_capacity: usize,
+    name: Option<String>,
+    closed: bool,
+    stats: Stats,
+    pop_wakers: WakerSet,
+    push_wakers: WakerSet,
+    waiting_consumers: usize,
+    waiting_producers: usize,
 }
 
+/// Which side of the queue a thread is blocked on.
+#[derive(Debug, Clone, Copy)]
+enum Side {
+    Consumer,
+    Producer,
+}
+
+/// Upper bound on the number of slots reserved up front, so that a huge or
+/// unbounded capacity does not allocate a huge heap.
+const MAX_PREALLOCATED: usize = 1024;
+
 impl<T: Ord> PriorityBlockingQueue<T> {
     pub fn new(max_capacity: usize) -> PriorityBlockingQueue<T> {
+        Self::with_compare(max_capacity, Compare::Fn(T::cmp))
+    }
+
+    /// Creates a queue without an element count limit.
+    pub fn new_unbounded() -> PriorityBlockingQueue<T> {
+        Self::new(usize::MAX)
+    }
+
+    /// Creates a queue that pops the smallest element first.
+    pub fn new_min(max_capacity: usize) -> PriorityBlockingQueue<T> {
+        Self::with_compare(max_capacity, Compare::Fn(|a, b| b.cmp(a)))
+    }
+}
+
+impl<T> PriorityBlockingQueue<T> {
+    /// Creates a queue that pops the greatest element according to `compare` first.
+    pub fn with_comparator<F>(max_capacity: usize, compare: F) -> PriorityBlockingQueue<T>
+    where
+        F: Fn(&T, &T) -> Ordering + Send + Sync + 'static,
+    {
+        Self::with_compare(max_capacity, Compare::Closure(Box::new(compare)))
+    }
+
+    /// Creates a queue that pops the element with the greatest key first.
+    pub fn with_key<K, F>(max_capacity: usize, key: F) -> PriorityBlockingQueue<T>
+    where
+        K: Ord,
+        F: Fn(&T) -> K + Send + Sync + 'static,
+    {
+        Self::with_comparator(max_capacity, move |a, b| key(a).cmp(&key(b)))
+    }
+
+    /// Sets how elements that compare equal are ordered, e.g. `TieBreak::Fifo`
+    /// to pop them in submission order. The default is `TieBreak::Arbitrary`.
+    pub fn with_tie_break(mut self, tie_break: TieBreak) -> PriorityBlockingQueue<T> {
+        sync::get_mut(&mut self.state).elements.set_tie_break(tie_break);
+        self
+    }
+
+    /// Names the queue in `tracing` events, to tell several queues apart.
+    pub fn with_name<S: Into<String>>(mut self, name: S) -> PriorityBlockingQueue<T> {
+        sync::get_mut(&mut self.state).name = Some(name.into());
+        self
+    }
+
+    /// Orders elements by an aging policy instead of the comparator, so that
+    /// elements gain priority the longer they wait.
+    pub fn with_aging(mut self, aging: Aging<T>) -> PriorityBlockingQueue<T> {
+        sync::get_mut(&mut self.state).elements.set_aging(aging);
+        self
+    }
+
+    /// Limits the total weight of queued elements to `max_weight`, on top of
+    /// the element count limit. A single element heavier than `max_weight` is
+    /// refused outright with `PushError::TooHeavy`.
+    ///
+    /// Weights are taken on push and retaken when an element is changed in
+    /// place, which never evicts or fails even if the limit is exceeded then.
+    pub fn with_weigher<F>(mut self, max_weight: usize, weigher: F) -> PriorityBlockingQueue<T>
+    where
+        F: Fn(&T) -> usize + Send + Sync + 'static,
+    {
+        self.max_weight = max_weight;
+        sync::get_mut(&mut self.state).elements.set_weigher(Box::new(weigher));
+        self
+    }
+
+    fn with_compare(max_capacity: usize, compare: Compare<T>) -> PriorityBlockingQueue<T> {
         PriorityBlockingQueue {
-            non_empty: (Mutex::new(false), Condvar::new()),
-            max_capacity,
-            elements: RwLock::new(BinaryHeap::with_capacity(max_capacity)),
+            non_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            max_weight: usize::MAX,
+            overflow: Overflow::Reject,
+            on_evict: None,
+            state: Mutex::new(State {
+                elements: Heap::with_capacity(max_capacity.min(MAX_PREALLOCATED), compare),
+                max_capacity,
+                name: None,
+                closed: false,
+                stats: Stats::default(),
+                pop_wakers: WakerSet::default(),
+                push_wakers: WakerSet::default(),
+                waiting_consumers: 0,
+                waiting_producers: 0,
+            }),
+        }
+    }
+
+    /// Locks the state, ignoring poisoning.
+    ///
+    /// A thread that panics under the lock, say in `Ord::cmp`, leaves every
+    /// queued element in place, including any it had already popped or
+    /// evicted, so the queue stays usable. Only the heap order may be off, and
+    /// it is rebuilt here. The element being pushed at the time may be lost.
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        let mut state = sync::lock(&self.state);
+        Self::repair(&mut state);
+        state
+    }
+
+    fn repair(state: &mut State<T>) {
+        if state.elements.is_dirty() {
+            event!(warn, queue = state.name.as_deref(), "repairing heap after a panic");
+            state.elements.repair();
         }
     }
 
     pub fn len(&self) -> usize {
-        self.elements.read().unwrap().len()
+        self.lock().elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().elements.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.lock().max_capacity
+    }
+
+    /// Changes the element count limit. Growing it wakes blocked producers for
+    /// the new room. Shrinking it below the current length evicts nothing: pushes
+    /// are refused, whatever the overflow policy, until the queue has drained
+    /// below the new limit.
+    pub fn set_capacity(&self, max_capacity: usize) {
+        let mut state = self.lock();
+        let grown = max_capacity.saturating_sub(state.max_capacity);
+        state.max_capacity = max_capacity;
+        if grown > 0 {
+            self.notify_waiters_for_pop(state, grown);
+        }
     }
 
-    pub fn push(&self, t: T) -> Result<(), Error> {
-        let mut elements = self.elements.write().unwrap();
-        if elements.len() >= self.max_capacity {
-            Err(Error::QueueCapacityReached)
+    /// Total weight of the queued elements, 0 unless the queue has a weigher.
+    pub fn weight(&self) -> usize {
+        self.lock().elements.weight()
+    }
+
+    /// Refuses `t` if the queue is closed or, failing that, if `t` alone
+    /// outweighs the weight limit. Every push checks in this order. Returns `t`
+    /// with its weight otherwise.
+    pub(crate) fn admit(&self, state: &mut State<T>, t: T) -> Result<(T, usize), PushError<T>> {
+        if state.closed {
+            return Err(PushError::Closed(t));
+        }
+        let weight = state.elements.weigh(&t);
+        if weight > self.max_weight {
+            Self::record_rejection(state, "too heavy");
+            return Err(PushError::TooHeavy(t));
+        }
+        Ok((t, weight))
+    }
+
+    /// Whether an element weighing `weight` fits in next to the queued ones.
+    fn has_room(&self, state: &State<T>, weight: usize) -> bool {
+        state.elements.len() < state.max_capacity && state.elements.weight().saturating_add(weight) <= self.max_weight
+    }
+
+    /// Closes the queue: further pushes fail with `PushError::Closed` and every
+    /// blocked producer and consumer is woken up. Elements already queued can
+    /// still be popped; once they are drained `pop` returns `None`.
+    pub fn close(&self) {
+        let mut state = self.lock();
+        state.closed = true;
+        event!(debug, queue = state.name.as_deref(), len = state.elements.len(), "closed");
+        let mut wakers = state.pop_wakers.take_all();
+        wakers.extend(state.push_wakers.take_all());
+        drop(state);
+        self.non_empty.notify_all();
+        self.not_full.notify_all();
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.lock().closed
+    }
+
+    /// Pushes `t`. What happens when the queue is full depends on its `Overflow`
+    /// policy; by default `t` is rejected.
+    pub fn push(&self, t: T) -> Result<(), PushError<T>> {
+        self.push_checked(t, false).map(|(_, evicted)| self.hand_off_evicted(evicted))
+    }
+
+    /// Like `push`, but returns a `Handle` for removing or updating `t` while it is queued.
+    pub fn push_with_handle(&self, t: T) -> Result<Handle, PushError<T>> {
+        self.push_checked(t, true).map(|(id, evicted)| {
+            self.hand_off_evicted(evicted);
+            Handle(id)
+        })
+    }
+
+    /// Pushes `t` under the overflow policy. Returns its id along with the
+    /// elements evicted to make room, if any.
+    fn push_checked(&self, t: T, tracked: bool) -> Result<(u64, Vec<T>), PushError<T>> {
+        let mut state = self.lock();
+        let (t, weight) = self.admit(&mut state, t)?;
+        if self.overflow == Overflow::Block {
+            state = self
+                .wait_while(Side::Producer, state, None, |state| {
+                    !state.closed && !self.has_room(state, weight)
+                })
+                .expect("waiting without a deadline never times out");
+        }
+        if state.closed {
+            return Err(PushError::Closed(t));
+        }
+        if self.has_room(&state, weight) {
+            return Ok((self.push_locked(state, t, tracked), Vec::new()));
+        }
+        // Without any capacity, evicting cannot make room. Over a shrunk
+        // capacity, the queue drains by pops rather than by mass eviction.
+        let evicting = matches!(self.overflow, Overflow::EvictLowest | Overflow::EvictIfOutranked);
+        if !evicting || state.max_capacity == 0 || state.elements.len() > state.max_capacity {
+            Self::record_rejection(&mut state, "full");
+            return Err(PushError::Full(t));
+        }
+        let (max_capacity, max_weight) = (state.max_capacity, self.max_weight);
+        let (id, evicted) = if self.overflow == Overflow::EvictLowest {
+            // `t` is known to fit into the empty queue, so this always ends up
+            // with room for it.
+            let evicted = state.elements.evict_until(|len, total| {
+                len < max_capacity && total + weight <= max_weight
+            });
+            (state.elements.push_entry(t, tracked), evicted)
+        } else {
+            state.elements.push_evicting_lower(t, tracked, |len, total| {
+                len <= max_capacity && total <= max_weight
+            })
+        };
+        state.stats.evicted += evicted.len() as u64;
+        event!(debug, queue = state.name.as_deref(), evicted = evicted.len(), "evicted to make room");
+        Self::record_push(&mut state, 1);
+        self.notify_waiters_for_push(state, 1);
+        Ok((id, evicted))
+    }
+
+    /// Pushes `t` if the queue is open and has free capacity, without blocking
+    /// or evicting.
+    pub fn try_push(&self, t: T) -> Result<(), PushError<T>> {
+        let mut state = self.lock();
+        let (t, weight) = self.admit(&mut state, t)?;
+        if !self.has_room(&state, weight) {
+            Self::record_rejection(&mut state, "full");
+            return Err(PushError::Full(t));
+        }
+        self.push_locked(state, t, false);
+        Ok(())
+    }
+
+    /// Pushes `t`, blocking until there is free capacity.
+    /// Fails if the queue is closed or `t` alone outweighs its limit.
+    pub fn put(&self, t: T) -> Result<(), PushError<T>> {
+        self.put_deadline(t, None)
+    }
+
+    /// Like `put`, but gives up with `PushError::Timeout` if no capacity frees
+    /// up within `timeout`. A timeout too large to represent as a deadline
+    /// waits forever.
+    pub fn put_timeout(&self, t: T, timeout: Duration) -> Result<(), PushError<T>> {
+        self.put_deadline(t, Instant::now().checked_add(timeout))
+    }
+
+    fn put_deadline(&self, t: T, deadline: Option<Instant>) -> Result<(), PushError<T>> {
+        let mut state = self.lock();
+        let (t, weight) = self.admit(&mut state, t)?;
+        match self.wait_while(Side::Producer, state, deadline, |state| {
+            !state.closed && !self.has_room(state, weight)
+        }) {
+            Some(state) if !state.closed => {
+                self.push_locked(state, t, false);
+                Ok(())
+            }
+            Some(_) => Err(PushError::Closed(t)),
+            None => {
+                Self::record_rejection(&mut self.lock(), "timeout");
+                Err(PushError::Timeout(t))
+            }
+        }
+    }
+
+    fn push_locked(&self, mut state: MutexGuard<State<T>>, t: T, tracked: bool) -> u64 {
+        let id = if tracked {
+            state.elements.push_tracked(t)
+        } else {
+            state.elements.push(t);
+            0
+        };
+        Self::record_push(&mut state, 1);
+        self.notify_waiters_for_push(state, 1);
+        id
+    }
+
+    /// Pushes elements from `elements` until the queue is full, taking the lock
+    /// only once. Returns the elements that did not fit, which is all of them if
+    /// the queue is closed.
+    ///
+    /// Elements that alone outweigh the weight limit could never fit. They are
+    /// skipped rather than ending the batch, and come first in the result.
+    pub fn push_all<I: IntoIterator<Item = T>>(&self, elements: I) -> Vec<T> {
+        let mut elements = elements.into_iter();
+        let mut state = self.lock();
+        let mut pushed = 0;
+        let mut too_heavy = Vec::new();
+        let mut rejected = None;
+        if !state.closed {
+            for t in elements.by_ref() {
+                let weight = state.elements.weigh(&t);
+                if weight > self.max_weight {
+                    Self::record_rejection(&mut state, "too heavy");
+                    too_heavy.push(t);
+                    continue;
+                }
+                if !self.has_room(&state, weight) {
+                    rejected = Some(t);
+                    break;
+                }
+                state.elements.push(t);
+                pushed += 1;
+            }
+        }
+        if rejected.is_some() {
+            Self::record_rejection(&mut state, "full");
+        }
+        if pushed > 0 {
+            Self::record_push(&mut state, pushed);
+            self.notify_waiters_for_push(state, pushed);
         } else {
-            elements.push(t);
-            self.notify_waiters_for_push();
-            Ok(())
+            drop(state);
         }
+        too_heavy.into_iter().chain(rejected).chain(elements).collect()
     }
 
-    fn notify_waiters_for_push(&self) {
-        let (mutex, non_empty_cond_var) = &self.non_empty;
-        let mut mutex_guard = mutex.lock().unwrap();
-        println!("Notifying on push");
-        *mutex_guard = true;
-        non_empty_cond_var.notify_one();
+    /// Whether the element behind `handle` is still queued.
+    pub fn contains(&self, handle: Handle) -> bool {
+        self.lock().elements.contains(handle.0)
     }
 
-    fn wait_non_empty(&self) {
-        println!("Waiting until non-empty");
-        let (mutex, non_empty_cond_var) = &self.non_empty;
-        let mutex_guard = mutex.lock().unwrap();
-        non_empty_cond_var.wait_while(mutex_guard, |non_empty| { !*non_empty });
+    /// Removes the element behind `handle` if it is still queued.
+    pub fn remove(&self, handle: Handle) -> Option<T> {
+        let mut state = self.lock();
+        let removed = state.elements.remove(handle.0);
+        if removed.is_some() {
+            self.notify_waiters_for_pop(state, 1);
+        }
+        removed
+    }
+
+    /// Applies `f` to the element behind `handle` and moves it to its new place
+    /// in priority order. Returns `false` if the element is no longer queued.
+    /// Blocked producers are woken if the change lowers the element's weight.
+    pub fn update<F: FnOnce(&mut T)>(&self, handle: Handle, f: F) -> bool {
+        let mut state = self.lock();
+        let weight = state.elements.weight();
+        let updated = state.elements.update(handle.0, f);
+        if state.elements.weight() < weight {
+            self.notify_waiters_for_pop(state, 1);
+        }
+        updated
     }
 
-    pub fn pop(&self) -> T {
-        self.wait_non_empty();
-        let mut elements = self.elements.write().unwrap();
-        elements.pop().unwrap()
+    /// Releases the lock after `count` elements were pushed and wakes as many
+    /// blocked consumers and waiting `PopFuture`s, as far as there are any.
+    fn notify_waiters_for_push(&self, mut state: MutexGuard<State<T>>, count: usize) {
+        let threads = count.min(state.waiting_consumers);
+        let wakers = state.pop_wakers.take(count);
+        drop(state);
+        for _ in 0..threads {
+            self.non_empty.notify_one();
+        }
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    /// Releases the lock after `count` slots were freed and wakes as many
+    /// blocked producers and waiting `PushFuture`s, as far as there are any.
+    /// With a weigher, freed weight may make room for any number of elements,
+    /// so all of them are woken.
+    fn notify_waiters_for_pop(&self, mut state: MutexGuard<State<T>>, count: usize) {
+        let count = if state.elements.is_weighed() { usize::MAX } else { count };
+        let threads = count.min(state.waiting_producers);
+        let wakers = state.push_wakers.take(count);
+        drop(state);
+        for _ in 0..threads {
+            self.not_full.notify_one();
+        }
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    /// Waits until the queue is non-empty or closed.
+    fn wait_non_empty<'a>(
+        &'a self,
+        state: MutexGuard<'a, State<T>>,
+        deadline: Option<Instant>,
+    ) -> Option<MutexGuard<'a, State<T>>> {
+        self.wait_while(Side::Consumer, state, deadline, |state| {
+            !state.closed && state.elements.is_empty()
+        })
+    }
+
+    /// Waits as `side` while `blocked` holds for the state or until `deadline` passes.
+    /// Returns `None` on timeout.
+    fn wait_while<'a>(
+        &'a self,
+        side: Side,
+        mut state: MutexGuard<'a, State<T>>,
+        deadline: Option<Instant>,
+        blocked: impl Fn(&State<T>) -> bool,
+    ) -> Option<MutexGuard<'a, State<T>>> {
+        if !blocked(&state) {
+            return Some(state);
+        }
+        let started = Instant::now();
+        while blocked(&state) {
+            let timeout = match deadline {
+                None => None,
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        let waited = started.elapsed();
+                        Self::record_wait(&mut state, side, waited);
+                        event!(debug, queue = state.name.as_deref(), side = ?side, waited = ?waited, "wait timed out");
+                        return None;
+                    }
+                    Some(deadline - now)
+                }
+            };
+            state = self.wait_once(side, state, timeout);
+        }
+        let waited = started.elapsed();
+        Self::record_wait(&mut state, side, waited);
+        event!(debug, queue = state.name.as_deref(), side = ?side, waited = ?waited, "wait ended");
+        Some(state)
+    }
+
+    fn record_wait(state: &mut State<T>, side: Side, waited: Duration) {
+        if let Side::Consumer = side {
+            state.stats.wait_time.observe(waited);
+        }
+    }
+
+    /// Blocks on the condition for `side` once, counted as one of its waiters
+    /// so that notifications can be limited to the threads actually waiting.
+    fn wait_once<'a>(
+        &'a self,
+        side: Side,
+        mut state: MutexGuard<'a, State<T>>,
+        timeout: Option<Duration>,
+    ) -> MutexGuard<'a, State<T>> {
+        let cond_var = match side {
+            Side::Consumer => {
+                state.waiting_consumers += 1;
+                &self.non_empty
+            }
+            Side::Producer => {
+                state.waiting_producers += 1;
+                &self.not_full
+            }
+        };
+        let mut state = sync::wait(cond_var, state, timeout);
+        Self::repair(&mut state);
+        match side {
+            Side::Consumer => state.waiting_consumers -= 1,
+            Side::Producer => state.waiting_producers -= 1,
+        }
+        state
+    }
+
+    /// Pops the greatest element, blocking while the queue is empty.
+    /// Returns `None` once the queue is closed and drained.
+    pub fn pop(&self) -> Option<T> {
+        let state = self.lock();
+        self.wait_non_empty(state, None).and_then(|state| self.pop_locked(state))
+    }
+
+    /// Pops the greatest element if there is one, without blocking.
+    pub fn try_pop(&self) -> Option<T> {
+        let state = self.lock();
+        self.pop_locked(state)
+    }
+
+    /// Like `pop`, but gives up with `PopError::Timeout` after `timeout`.
+    /// A timeout too large to represent as a deadline waits forever.
+    pub fn pop_timeout(&self, timeout: Duration) -> Result<T, PopError> {
+        self.pop_until(Instant::now().checked_add(timeout))
+    }
+
+    /// Like `pop`, but gives up with `PopError::Timeout` once `deadline` passes.
+    /// Fails with `PopError::Closed` once the queue is closed and drained.
+    pub fn pop_deadline(&self, deadline: Instant) -> Result<T, PopError> {
+        self.pop_until(Some(deadline))
+    }
+
+    fn pop_until(&self, deadline: Option<Instant>) -> Result<T, PopError> {
+        let state = self.lock();
+        match self.wait_non_empty(state, deadline) {
+            Some(state) => self.pop_locked(state).ok_or(PopError::Closed),
+            None => Err(PopError::Timeout),
+        }
+    }
+
+    /// Blocks until there is at least one element, then pops up to `max_n`
+    /// elements in priority order under a single lock acquisition. Returns an
+    /// empty `Vec` once the queue is closed and drained.
+    pub fn pop_batch(&self, max_n: usize) -> Vec<T> {
+        self.pop_batch_deadline(max_n, None).unwrap_or_default()
+    }
+
+    /// Like `pop_batch`, but gives up with `PopError::Timeout` after `timeout`.
+    /// Fails with `PopError::Closed` once the queue is closed and drained. A
+    /// timeout too large to represent as a deadline waits forever.
+    pub fn pop_batch_timeout(&self, max_n: usize, timeout: Duration) -> Result<Vec<T>, PopError> {
+        self.pop_batch_deadline(max_n, Instant::now().checked_add(timeout))
+    }
+
+    fn pop_batch_deadline(&self, max_n: usize, deadline: Option<Instant>) -> Result<Vec<T>, PopError> {
+        if max_n == 0 {
+            return Ok(Vec::new());
+        }
+        let state = self.lock();
+        let mut state = self.wait_non_empty(state, deadline).ok_or(PopError::Timeout)?;
+        let popped: Vec<_> = state
+            .elements
+            .pop_many(max_n)
+            .into_iter()
+            .map(|(t, enqueued)| {
+                Self::record_pop(&mut state, enqueued);
+                t
+            })
+            .collect();
+        if popped.is_empty() {
+            return Err(PopError::Closed);
+        }
+        self.notify_waiters_for_pop(state, popped.len());
+        Ok(popped)
+    }
+
+    /// Removes and returns every queued element, in no particular order.
+    pub fn drain(&self) -> Vec<T> {
+        let mut state = self.lock();
+        let drained = state.elements.drain();
+        self.notify_waiters_for_pop(state, drained.len());
+        drained
+    }
+
+    /// Removes and returns every queued element, in the order `pop` would return them.
+    pub fn drain_sorted(&self) -> Vec<T> {
+        let mut state = self.lock();
+        let drained = state.elements.drain_sorted();
+        self.notify_waiters_for_pop(state, drained.len());
+        drained
+    }
+
+    /// Consumes the queue and returns its elements in ascending order, like
+    /// `BinaryHeap::into_sorted_vec`.
+    pub fn into_sorted_vec(self) -> Vec<T> {
+        let mut sorted = sync::into_inner(self.state).elements.drain_sorted();
+        sorted.reverse();
+        sorted
+    }
+
+    pub fn clear(&self) {
+        self.drain();
+    }
+
+    /// Keeps only the elements `keep` returns `true` for.
+    pub fn retain<F: FnMut(&T) -> bool>(&self, mut keep: F) {
+        self.remove_where(|t| !keep(t));
+    }
+
+    /// Removes and returns every element `remove` returns `true` for, in no
+    /// particular order. Blocked producers are woken for the freed capacity.
+    pub fn remove_where<F: FnMut(&T) -> bool>(&self, remove: F) -> Vec<T> {
+        let mut state = self.lock();
+        let removed = state.elements.remove_where(remove);
+        self.notify_waiters_for_pop(state, removed.len());
+        removed
+    }
+
+    pub fn stats(&self) -> Stats {
+        let state = self.lock();
+        Stats {
+            len: state.elements.len(),
+            blocked_consumers: state.waiting_consumers + state.pop_wakers.len(),
+            blocked_producers: state.waiting_producers + state.push_wakers.len(),
+            ..state.stats.clone()
+        }
+    }
+
+    fn pop_locked(&self, mut state: MutexGuard<State<T>>) -> Option<T> {
+        let popped = Self::pop_one(&mut state);
+        if popped.is_some() {
+            self.notify_waiters_for_pop(state, 1);
+        }
+        popped
+    }
+
+    fn pop_one(state: &mut State<T>) -> Option<T> {
+        let (t, enqueued) = state.elements.pop_timed()?;
+        Self::record_pop(state, enqueued);
+        Some(t)
+    }
+
+    fn record_pop(state: &mut State<T>, enqueued: Instant) {
+        let waited = enqueued.elapsed();
+        if waited > state.stats.max_wait {
+            state.stats.max_wait = waited;
+        }
+        state.stats.popped += 1;
+        state.stats.sojourn_time.observe(waited);
+        event!(trace, queue = state.name.as_deref(), len = state.elements.len(), waited = ?waited, "popped");
+    }
+
+    fn record_push(state: &mut State<T>, count: usize) {
+        let len = state.elements.len();
+        state.stats.pushed += count as u64;
+        state.stats.high_water_mark = state.stats.high_water_mark.max(len);
+        event!(trace, queue = state.name.as_deref(), pushed = count, len, "pushed");
+    }
+
+    /// Counts a push refused for lack of room.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub(crate) fn record_rejection(state: &mut State<T>, reason: &'static str) {
+        state.stats.rejected += 1;
+        event!(debug, queue = state.name.as_deref(), len = state.elements.len(), reason, "rejected push");
     }
 }
 
-pub enum Error {
-    QueueCapacityReached
+impl<T: Clone> PriorityBlockingQueue<T> {
+    /// Copies the queued elements, in the order `pop` would return them, without
+    /// removing anything.
+    pub fn snapshot(&self) -> Vec<T> {
+        let mut state = self.lock();
+        state.elements.sorted().into_iter().cloned().collect()
+    }
 }
 
+/// Refers to an element pushed with `push_with_handle`. Handles are only
+/// meaningful for the queue that issued them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Handle(u64);
+
+
 
 #[cfg(test)]
 mod tests {
     use std::thread;
-    use crate::PriorityBlockingQueue;
+    use crate::{sync, Aging, Overflow, PopError, PriorityBlockingQueue, PushError, TieBreak};
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
     use std::sync::Arc;
     use std::time::{Duration, Instant};
 
@@ -75,13 +740,13 @@ mod tests {
     #[test]
     fn it_should_push_and_pop_elements_from_the_queue() {
         let q = PriorityBlockingQueue::new(10);
-        q.push(3);
-        q.push(4);
-        q.push(2);
+        q.push(3).unwrap();
+        q.push(4).unwrap();
+        q.push(2).unwrap();
 
-        assert_eq!(q.pop(), 4);
-        assert_eq!(q.pop(), 3);
-        assert_eq!(q.pop(), 2);
+        assert_eq!(q.pop(), Some(4));
+        assert_eq!(q.pop(), Some(3));
+        assert_eq!(q.pop(), Some(2));
     }
 
     #[test]
@@ -91,11 +756,11 @@ mod tests {
         let start = Instant::now();
         thread::spawn(move || {
             thread::sleep(Duration::from_secs(3));
-            q_clone.push(1);
+            q_clone.push(1).unwrap();
         });
         let popped = q.pop();
         let elapsed = start.elapsed().as_secs();
-        assert_eq!(popped, 1);
+        assert_eq!(popped, Some(1));
         assert!(elapsed >= 2);
     }
 
@@ -105,18 +770,731 @@ mod tests {
         assert!(q.push(1).is_ok());
         assert!(q.push(2).is_ok());
         assert!(q.push(3).is_err());
-        assert_eq!(q.pop(), 2);
+        assert_eq!(q.pop(), Some(2));
         assert!(q.push(3).is_ok());
     }
 
+    #[test]
+    fn it_should_return_none_on_try_pop_from_empty_queue() {
+        let q = PriorityBlockingQueue::new(10);
+        assert_eq!(q.try_pop(), None);
+        q.push(1).unwrap();
+        assert_eq!(q.try_pop(), Some(1));
+        assert_eq!(q.try_pop(), None);
+    }
+
+    #[test]
+    fn it_should_time_out_on_pop_from_empty_queue() {
+        let q = PriorityBlockingQueue::<i32>::new(10);
+        let start = Instant::now();
+        assert_eq!(q.pop_timeout(Duration::from_millis(200)), Err(PopError::Timeout));
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn it_should_wait_forever_on_pop_with_unrepresentable_timeout() {
+        let q = Arc::new(PriorityBlockingQueue::new(10));
+        q.push(1).unwrap();
+        assert_eq!(q.pop_timeout(Duration::MAX), Ok(1));
+        let q_clone = Arc::clone(&q);
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            q_clone.push(2).unwrap();
+        });
+        assert_eq!(q.pop_timeout(Duration::MAX), Ok(2));
+        q.close();
+        assert_eq!(q.pop_timeout(Duration::MAX), Err(PopError::Closed));
+    }
+
+    #[test]
+    fn it_should_pop_before_deadline_when_element_arrives() {
+        let q = Arc::new(PriorityBlockingQueue::new(10));
+        let q_clone = Arc::clone(&q);
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            q_clone.push(1).unwrap();
+        });
+        assert_eq!(q.pop_deadline(Instant::now() + Duration::from_secs(5)), Ok(1));
+    }
+
+    #[test]
+    fn it_should_hand_back_element_on_try_push_when_capacity_reached() {
+        let q = PriorityBlockingQueue::new(1);
+        assert_eq!(q.try_push(1), Ok(()));
+        assert_eq!(q.try_push(2), Err(PushError::Full(2)));
+    }
+
+    #[test]
+    fn it_should_block_on_put_until_capacity_frees_up() {
+        let q = Arc::new(PriorityBlockingQueue::new(1));
+        q.put(1).unwrap();
+        let q_clone = Arc::clone(&q);
+        let start = Instant::now();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            assert_eq!(q_clone.pop(), Some(1));
+        });
+        q.put(2).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert_eq!(q.pop(), Some(2));
+    }
+
+    #[test]
+    fn it_should_time_out_on_put_to_full_queue() {
+        let q = PriorityBlockingQueue::new(1);
+        q.put(1).unwrap();
+        assert_eq!(q.put_timeout(2, Duration::from_millis(100)), Err(PushError::Timeout(2)));
+        assert_eq!(q.len(), 1);
+    }
+
+    #[test]
+    fn it_should_wait_forever_on_put_with_unrepresentable_timeout() {
+        let q = Arc::new(PriorityBlockingQueue::new(1));
+        assert_eq!(q.put_timeout(1, Duration::from_secs(u64::MAX)), Ok(()));
+        let q_clone = Arc::clone(&q);
+        let producer = thread::spawn(move || q_clone.put_timeout(2, Duration::from_secs(u64::MAX)));
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(q.pop(), Some(1));
+        assert_eq!(producer.join().unwrap(), Ok(()));
+        assert_eq!(q.pop(), Some(2));
+    }
+
+    #[test]
+    fn it_should_block_on_pop_after_queue_drains() {
+        let q = PriorityBlockingQueue::new(10);
+        q.push(1).unwrap();
+        assert_eq!(q.pop(), Some(1));
+        assert_eq!(q.pop_timeout(Duration::from_millis(100)), Err(PopError::Timeout));
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn it_should_give_single_element_to_exactly_one_of_two_consumers() {
+        let q = Arc::new(PriorityBlockingQueue::new(10));
+        let consumers: Vec<_> = (0..2)
+            .map(|_| {
+                let q = Arc::clone(&q);
+                thread::spawn(move || q.pop_timeout(Duration::from_millis(500)).ok())
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(100));
+        q.push(1).unwrap();
+        let popped: Vec<_> = consumers.into_iter().filter_map(|c| c.join().unwrap()).collect();
+        assert_eq!(popped, vec![1]);
+    }
+
+    #[test]
+    fn it_should_not_lose_elements_with_many_producers_and_consumers() {
+        const PRODUCERS: usize = 8;
+        const CONSUMERS: usize = 8;
+        const PER_PRODUCER: usize = 1000;
+        let q = Arc::new(PriorityBlockingQueue::new(16));
+        let producers: Vec<_> = (0..PRODUCERS)
+            .map(|p| {
+                let q = Arc::clone(&q);
+                thread::spawn(move || {
+                    for i in 0..PER_PRODUCER {
+                        q.put(p * PER_PRODUCER + i).unwrap();
+                    }
+                })
+            })
+            .collect();
+        let consumers: Vec<_> = (0..CONSUMERS)
+            .map(|_| {
+                let q = Arc::clone(&q);
+                thread::spawn(move || {
+                    (0..PRODUCERS * PER_PRODUCER / CONSUMERS).map(|_| q.pop().unwrap()).collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        let mut popped: Vec<_> = consumers.into_iter().flat_map(|c| c.join().unwrap()).collect();
+        popped.sort_unstable();
+        assert_eq!(popped, (0..PRODUCERS * PER_PRODUCER).collect::<Vec<_>>());
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn it_should_reject_push_after_close() {
+        let q = PriorityBlockingQueue::new(10);
+        q.close();
+        assert!(q.is_closed());
+        assert_eq!(q.push(1), Err(PushError::Closed(1)));
+        assert_eq!(q.try_push(1), Err(PushError::Closed(1)));
+        assert_eq!(q.put(1), Err(PushError::Closed(1)));
+    }
+
+    #[test]
+    fn it_should_drain_remaining_elements_after_close() {
+        let q = PriorityBlockingQueue::new(10);
+        q.push(1).unwrap();
+        q.push(2).unwrap();
+        q.close();
+        assert_eq!(q.pop(), Some(2));
+        assert_eq!(q.pop_timeout(Duration::from_secs(1)), Ok(1));
+        assert_eq!(q.pop(), None);
+        assert_eq!(q.pop_timeout(Duration::from_secs(1)), Err(PopError::Closed));
+    }
+
+    #[test]
+    fn it_should_wake_blocked_consumers_and_producers_on_close() {
+        let q = Arc::new(PriorityBlockingQueue::new(1));
+        let consumers: Vec<_> = (0..3)
+            .map(|_| {
+                let q = Arc::clone(&q);
+                thread::spawn(move || q.pop())
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(100));
+        q.close();
+        for consumer in consumers {
+            assert_eq!(consumer.join().unwrap(), None::<i32>);
+        }
+
+        let q = Arc::new(PriorityBlockingQueue::new(0));
+        let q_clone = Arc::clone(&q);
+        let producer = thread::spawn(move || q_clone.put(1));
+        thread::sleep(Duration::from_millis(100));
+        q.close();
+        assert_eq!(producer.join().unwrap(), Err(PushError::Closed(1)));
+    }
+
+    #[test]
+    fn it_should_pop_smallest_first_in_min_mode() {
+        let q = PriorityBlockingQueue::new_min(10);
+        q.push(3).unwrap();
+        q.push(1).unwrap();
+        q.push(2).unwrap();
+        assert_eq!(q.pop(), Some(1));
+        assert_eq!(q.pop(), Some(2));
+        assert_eq!(q.pop(), Some(3));
+    }
+
+    #[test]
+    fn it_should_order_by_comparator_and_key() {
+        #[derive(Debug, PartialEq)]
+        struct Job {
+            name: &'static str,
+            deadline: u32,
+        }
+
+        let q = PriorityBlockingQueue::with_comparator(10, |a: &Job, b: &Job| b.deadline.cmp(&a.deadline));
+        q.push(Job { name: "late", deadline: 20 }).unwrap();
+        q.push(Job { name: "early", deadline: 10 }).unwrap();
+        assert_eq!(q.pop().unwrap().name, "early");
+
+        let q = PriorityBlockingQueue::with_key(10, |job: &Job| job.name.len());
+        q.push(Job { name: "short", deadline: 0 }).unwrap();
+        q.push(Job { name: "longest", deadline: 0 }).unwrap();
+        assert_eq!(q.pop().unwrap().name, "longest");
+    }
+
+    #[test]
+    fn it_should_pop_equal_priorities_in_submission_order() {
+        let q = PriorityBlockingQueue::with_key(1000, |job: &(u8, usize)| job.0).with_tie_break(TieBreak::Fifo);
+        for i in 0..1000 {
+            q.push(((i % 2) as u8, i)).unwrap();
+        }
+        let popped: Vec<_> = std::iter::from_fn(|| q.try_pop()).map(|(_, i)| i).collect();
+        let expected: Vec<_> = (1..1000).step_by(2).chain((0..1000).step_by(2)).collect();
+        assert_eq!(popped, expected);
+    }
+
+    #[test]
+    fn it_should_remove_queued_element_by_handle() {
+        let q = PriorityBlockingQueue::new(2);
+        let handle = q.push_with_handle(1).unwrap();
+        q.push(2).unwrap();
+        assert!(q.contains(handle));
+        assert_eq!(q.remove(handle), Some(1));
+        assert!(!q.contains(handle));
+        assert_eq!(q.remove(handle), None);
+        q.push(3).unwrap();
+        assert_eq!(q.pop(), Some(3));
+        assert_eq!(q.pop(), Some(2));
+    }
+
+    #[test]
+    fn it_should_reorder_element_updated_by_handle() {
+        let q = PriorityBlockingQueue::new(10);
+        q.push(5).unwrap();
+        let handle = q.push_with_handle(1).unwrap();
+        assert!(q.update(handle, |value| *value = 10));
+        assert_eq!(q.pop(), Some(10));
+        assert!(!q.update(handle, |value| *value = 20));
+        assert_eq!(q.pop(), Some(5));
+    }
+
+    #[test]
+    fn it_should_let_long_waiting_elements_overtake_with_linear_aging() {
+        let q = PriorityBlockingQueue::new(10).with_aging(Aging::linear(|t: &u32| f64::from(*t), 10.0));
+        q.push(0).unwrap();
+        thread::sleep(Duration::from_millis(300));
+        q.push(2).unwrap();
+        assert_eq!(q.pop(), Some(0));
+        assert_eq!(q.pop(), Some(2));
+        assert!(q.stats().max_wait >= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn it_should_count_pushes_pops_rejections_and_blocked_consumers() {
+        let q = Arc::new(PriorityBlockingQueue::new(3));
+        assert_eq!(q.push_all(vec![1, 2, 3, 4]), vec![4]);
+        assert_eq!(q.try_push(5), Err(PushError::Full(5)));
+        assert_eq!(q.pop_batch(3), vec![3, 2, 1]);
+        let consumer = {
+            let q = Arc::clone(&q);
+            thread::spawn(move || q.pop())
+        };
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(q.stats().blocked_consumers, 1);
+        q.push(6).unwrap();
+        assert_eq!(consumer.join().unwrap(), Some(6));
+
+        let stats = q.stats();
+        assert_eq!((stats.len, stats.high_water_mark), (0, 3));
+        assert_eq!((stats.pushed, stats.popped, stats.rejected), (4, 4, 2));
+        assert_eq!((stats.blocked_consumers, stats.blocked_producers), (0, 0));
+        assert_eq!(stats.sojourn_time.count(), 4);
+        assert_eq!(stats.wait_time.count(), 1);
+        assert!(stats.wait_time.sum() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn it_should_refresh_custom_aging_periodically() {
+        let aging = Aging::custom(
+            |t: &u32, waited: Duration| if waited >= Duration::from_millis(200) { 100.0 } else { f64::from(*t) },
+            Duration::from_millis(50),
+        );
+        let q = PriorityBlockingQueue::new(10).with_aging(aging);
+        q.push(1).unwrap();
+        q.push(3).unwrap();
+        thread::sleep(Duration::from_millis(250));
+        q.push(2).unwrap();
+        assert_eq!(q.pop().map(|t| t == 1 || t == 3), Some(true));
+        assert_eq!(q.len(), 2);
+        assert_eq!(q.pop().map(|t| t == 1 || t == 3), Some(true));
+        assert_eq!(q.pop(), Some(2));
+    }
+
+    #[test]
+    fn it_should_push_all_that_fits_and_return_the_rest() {
+        let q = PriorityBlockingQueue::new(3);
+        q.push(10).unwrap();
+        assert_eq!(q.push_all(vec![1, 2, 3, 4]), vec![3, 4]);
+        assert_eq!(q.pop_batch(10), vec![10, 2, 1]);
+        q.close();
+        assert_eq!(q.push_all(vec![5]), vec![5]);
+    }
+
+    #[test]
+    fn it_should_pop_batch_in_priority_order() {
+        let q = PriorityBlockingQueue::new(10);
+        assert!(q.push_all(vec![3, 1, 4, 1, 5]).is_empty());
+        assert_eq!(q.pop_batch(3), vec![5, 4, 3]);
+        assert_eq!(q.pop_batch_timeout(3, Duration::from_secs(1)), Ok(vec![1, 1]));
+        assert_eq!(q.pop_batch_timeout(3, Duration::from_millis(50)), Err(PopError::Timeout));
+        q.push(9).unwrap();
+        assert_eq!(q.pop_batch_timeout(3, Duration::MAX), Ok(vec![9]));
+        q.close();
+        assert_eq!(q.pop_batch(3), Vec::<i32>::new());
+        assert_eq!(q.pop_batch_timeout(3, Duration::from_millis(50)), Err(PopError::Closed));
+        assert_eq!(q.pop_batch_timeout(3, Duration::MAX), Err(PopError::Closed));
+    }
+
+    #[test]
+    fn it_should_wake_one_consumer_per_element_of_batch_push() {
+        let q = Arc::new(PriorityBlockingQueue::new(10));
+        let consumers: Vec<_> = (0..3)
+            .map(|_| {
+                let q = Arc::clone(&q);
+                thread::spawn(move || q.pop_timeout(Duration::from_secs(5)))
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(100));
+        assert!(q.push_all(vec![1, 2, 3]).is_empty());
+        let mut popped: Vec<_> = consumers.into_iter().map(|c| c.join().unwrap().unwrap()).collect();
+        popped.sort_unstable();
+        assert_eq!(popped, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn it_should_wake_blocked_producers_after_pop_batch() {
+        let q = Arc::new(PriorityBlockingQueue::new(2));
+        assert!(q.push_all(vec![1, 2]).is_empty());
+        let producers: Vec<_> = (3..5)
+            .map(|t| {
+                let q = Arc::clone(&q);
+                thread::spawn(move || q.put_timeout(t, Duration::from_secs(5)))
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(q.pop_batch(2), vec![2, 1]);
+        for producer in producers {
+            assert_eq!(producer.join().unwrap(), Ok(()));
+        }
+        assert_eq!(q.len(), 2);
+    }
+
+    #[test]
+    fn it_should_drain_and_clear_without_blocking() {
+        let q = PriorityBlockingQueue::new(10);
+        assert!(q.push_all(vec![2, 3, 1]).is_empty());
+        let mut drained = q.drain();
+        drained.sort_unstable();
+        assert_eq!(drained, vec![1, 2, 3]);
+        assert!(q.drain().is_empty());
+
+        assert!(q.push_all(vec![2, 3, 1]).is_empty());
+        assert_eq!(q.drain_sorted(), vec![3, 2, 1]);
+
+        assert!(q.push_all(vec![2, 3, 1]).is_empty());
+        q.clear();
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn it_should_snapshot_in_pop_order_without_removing() {
+        let q = PriorityBlockingQueue::with_key(10, |job: &(u8, char)| job.0).with_tie_break(TieBreak::Fifo);
+        assert!(q.push_all(vec![(1, 'a'), (2, 'b'), (1, 'c'), (3, 'd')]).is_empty());
+        assert_eq!(q.snapshot(), vec![(3, 'd'), (2, 'b'), (1, 'a'), (1, 'c')]);
+        assert_eq!(q.len(), 4);
+        assert_eq!(q.into_sorted_vec(), vec![(1, 'c'), (1, 'a'), (2, 'b'), (3, 'd')]);
+    }
+
+    #[test]
+    fn it_should_free_capacity_for_blocked_producer_on_drain() {
+        let q = Arc::new(PriorityBlockingQueue::new(1));
+        q.push(1).unwrap();
+        let q_clone = Arc::clone(&q);
+        let producer = thread::spawn(move || q_clone.put_timeout(2, Duration::from_secs(5)));
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(q.drain(), vec![1]);
+        assert_eq!(producer.join().unwrap(), Ok(()));
+        assert_eq!(q.pop(), Some(2));
+    }
+
+    #[test]
+    fn it_should_remove_matching_elements_and_keep_order() {
+        let q = PriorityBlockingQueue::new(100);
+        assert!(q.push_all(0..100).is_empty());
+        let mut removed = q.remove_where(|t| t % 3 == 0);
+        removed.sort_unstable();
+        assert_eq!(removed, (0..100).step_by(3).collect::<Vec<_>>());
+        q.retain(|t| t % 2 == 0);
+        let expected: Vec<_> = (0..100).rev().filter(|t| t % 3 != 0 && t % 2 == 0).collect();
+        assert_eq!(q.drain_sorted(), expected);
+    }
+
+    #[test]
+    fn it_should_keep_handles_valid_across_retain() {
+        let q = PriorityBlockingQueue::new(10);
+        let handles: Vec<_> = (0..6).map(|t| q.push_with_handle(t).unwrap()).collect();
+        q.retain(|t| t % 2 == 1);
+        assert!(!q.contains(handles[0]));
+        assert_eq!(q.remove(handles[3]), Some(3));
+        assert!(q.update(handles[1], |t| *t = 10));
+        assert_eq!(q.drain_sorted(), vec![10, 5]);
+    }
+
+    #[test]
+    fn it_should_wake_blocked_producer_after_remove_where() {
+        let q = Arc::new(PriorityBlockingQueue::new(2));
+        assert!(q.push_all(vec![1, 2]).is_empty());
+        let q_clone = Arc::clone(&q);
+        let producer = thread::spawn(move || q_clone.put_timeout(3, Duration::from_secs(5)));
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(q.remove_where(|t| *t == 1), vec![1]);
+        assert_eq!(producer.join().unwrap(), Ok(()));
+        assert_eq!(q.drain_sorted(), vec![3, 2]);
+    }
+
+    #[test]
+    fn it_should_limit_total_weight_and_refuse_too_heavy_elements() {
+        let q = PriorityBlockingQueue::new(100).with_weigher(10, |t: &usize| *t);
+        q.push(4).unwrap();
+        q.push(5).unwrap();
+        assert_eq!(q.push(2), Err(PushError::Full(2)));
+        assert_eq!(q.try_push(1), Ok(()));
+        assert_eq!(q.weight(), 10);
+        assert_eq!(q.push(11), Err(PushError::TooHeavy(11)));
+        assert_eq!(q.put(11), Err(PushError::TooHeavy(11)));
+        assert_eq!(q.pop(), Some(5));
+        assert_eq!(q.weight(), 5);
+        assert_eq!(q.push_all(vec![3, 2, 1]), vec![1]);
+        q.clear();
+        assert_eq!(q.weight(), 0);
+    }
+
+    #[test]
+    fn it_should_wake_blocked_producers_when_a_change_frees_weight() {
+        let q = Arc::new(PriorityBlockingQueue::new(10).with_weigher(10, |t: &(u8, usize)| t.1));
+        let handle = q.push_with_handle((1, 9)).unwrap();
+        let start = Instant::now();
+        let producer = {
+            let q = Arc::clone(&q);
+            thread::spawn(move || q.put_timeout((2, 5), Duration::from_secs(5)))
+        };
+        thread::sleep(Duration::from_millis(100));
+        assert!(q.update(handle, |t| t.1 = 1));
+        assert_eq!(producer.join().unwrap(), Ok(()));
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        let producer = {
+            let q = Arc::clone(&q);
+            thread::spawn(move || q.put_timeout((3, 5), Duration::from_secs(5)))
+        };
+        thread::sleep(Duration::from_millis(100));
+        q.peek_mut().unwrap().1 = 0;
+        assert_eq!(producer.join().unwrap(), Ok(()));
+        assert!(start.elapsed() < Duration::from_secs(4));
+        assert_eq!(q.weight(), 6);
+    }
+
+    #[test]
+    fn it_should_refuse_pushes_into_closed_queue_before_weighing() {
+        let q = PriorityBlockingQueue::new(10).with_weigher(10, |t: &usize| *t);
+        q.close();
+        assert_eq!(q.push(11), Err(PushError::Closed(11)));
+        assert_eq!(q.put(11), Err(PushError::Closed(11)));
+        assert_eq!(q.try_push(11), Err(PushError::Closed(11)));
+        assert_eq!(futures::executor::block_on(q.push_async(11)), Err(PushError::Closed(11)));
+        assert_eq!(q.stats().rejected, 0);
+    }
+
+    #[test]
+    fn it_should_skip_too_heavy_elements_in_push_all() {
+        let q = PriorityBlockingQueue::new(100).with_weigher(5, |t: &usize| *t);
+        assert_eq!(q.push_all(vec![1, 10, 2, 3, 6]), vec![10, 3, 6]);
+        assert_eq!(q.drain_sorted(), vec![2, 1]);
+        assert_eq!(q.stats().rejected, 2);
+    }
+
+    #[test]
+    fn it_should_wake_every_producer_that_fits_after_heavy_pop() {
+        let q = Arc::new(PriorityBlockingQueue::new(100).with_weigher(10, |t: &usize| *t));
+        q.push(9).unwrap();
+        let producers: Vec<_> = (0..2)
+            .map(|_| {
+                let q = Arc::clone(&q);
+                thread::spawn(move || q.put_timeout(3, Duration::from_secs(5)))
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(q.pop(), Some(9));
+        for producer in producers {
+            assert_eq!(producer.join().unwrap(), Ok(()));
+        }
+        assert_eq!(q.weight(), 6);
+    }
+
+    #[test]
+    fn it_should_wake_blocked_producers_when_capacity_grows() {
+        let q = Arc::new(PriorityBlockingQueue::new(1));
+        q.push(1).unwrap();
+        let producers: Vec<_> = (2..4)
+            .map(|t| {
+                let q = Arc::clone(&q);
+                thread::spawn(move || q.put_timeout(t, Duration::from_secs(5)))
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(100));
+        q.set_capacity(3);
+        for producer in producers {
+            assert_eq!(producer.join().unwrap(), Ok(()));
+        }
+        assert_eq!(q.capacity(), 3);
+        assert_eq!(q.len(), 3);
+    }
+
+    #[test]
+    fn it_should_refuse_pushes_until_drained_below_shrunk_capacity() {
+        let q = PriorityBlockingQueue::new(5);
+        assert!(q.push_all(1..=5).is_empty());
+        q.set_capacity(2);
+        assert_eq!(q.push(6), Err(PushError::Full(6)));
+        assert_eq!(q.pop_batch(3), vec![5, 4, 3]);
+        assert_eq!(q.push(6), Err(PushError::Full(6)));
+        assert_eq!(q.pop(), Some(2));
+        assert_eq!(q.push(6), Ok(()));
+    }
+
+    #[test]
+    fn it_should_accept_any_number_of_elements_when_unbounded() {
+        let q = PriorityBlockingQueue::new_unbounded();
+        assert!(q.push_all(0..10_000).is_empty());
+        assert_eq!(q.capacity(), usize::MAX);
+        assert_eq!(q.pop(), Some(9_999));
+    }
+
     #[test]
     fn it_should_handle_boxed_values() {
         let q = PriorityBlockingQueue::new(10);
-        q.push(Box::new(2));
-        q.push(Box::new(1));
-        q.push(Box::new(3));
-        assert_eq!(*q.pop(), 3);
-        assert_eq!(*q.pop(), 2);
-        assert_eq!(*q.pop(), 1);
+        q.push(Box::new(2)).unwrap();
+        q.push(Box::new(1)).unwrap();
+        q.push(Box::new(3)).unwrap();
+        assert_eq!(*q.pop().unwrap(), 3);
+        assert_eq!(*q.pop().unwrap(), 2);
+        assert_eq!(*q.pop().unwrap(), 1);
+    }
+
+    /// Compares by `value`, but panics on the n-th comparison after `armed` is set to n.
+    #[derive(Debug)]
+    struct Touchy {
+        value: u32,
+        armed: Arc<AtomicUsize>,
+    }
+
+    impl PartialEq for Touchy {
+        fn eq(&self, other: &Self) -> bool {
+            self.cmp(other) == std::cmp::Ordering::Equal
+        }
+    }
+
+    impl Eq for Touchy {}
+
+    impl PartialOrd for Touchy {
+        fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for Touchy {
+        fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+            if self.armed.fetch_update(AtomicOrdering::SeqCst, AtomicOrdering::SeqCst, |n| n.checked_sub(1)) == Ok(1) {
+                panic!("comparison failed");
+            }
+            self.value.cmp(&other.value)
+        }
+    }
+
+    fn touchy_queue(values: &[u32]) -> (Arc<PriorityBlockingQueue<Touchy>>, Arc<AtomicUsize>) {
+        fill_touchy(PriorityBlockingQueue::new(10), values)
+    }
+
+    fn fill_touchy(
+        q: PriorityBlockingQueue<Touchy>,
+        values: &[u32],
+    ) -> (Arc<PriorityBlockingQueue<Touchy>>, Arc<AtomicUsize>) {
+        let armed = Arc::new(AtomicUsize::new(0));
+        for &value in values {
+            q.push(Touchy { value, armed: Arc::clone(&armed) }).unwrap();
+        }
+        (Arc::new(q), armed)
+    }
+
+    fn drain_touchy(q: &PriorityBlockingQueue<Touchy>) -> Vec<u32> {
+        std::iter::from_fn(|| q.try_pop()).map(|t| t.value).collect()
+    }
+
+    #[test]
+    fn it_should_stay_usable_after_panic_in_cmp_during_push() {
+        let (q, armed) = touchy_queue(&[3, 1, 4, 2]);
+        armed.store(1, AtomicOrdering::SeqCst);
+        let pusher = {
+            let (q, armed) = (Arc::clone(&q), Arc::clone(&armed));
+            thread::spawn(move || q.push(Touchy { value: 5, armed }))
+        };
+        assert!(pusher.join().is_err());
+        q.push(Touchy { value: 0, armed }).unwrap();
+        let drained: Vec<_> = q.drain_sorted().into_iter().map(|t| t.value).collect();
+        assert_eq!(drained, vec![5, 4, 3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn it_should_stay_usable_after_panic_in_cmp_during_pop() {
+        let (q, armed) = touchy_queue(&[8, 3, 9, 1, 6, 2, 7, 4, 5]);
+        armed.store(1, AtomicOrdering::SeqCst);
+        let popper = {
+            let q = Arc::clone(&q);
+            thread::spawn(move || q.pop().map(|t| t.value))
+        };
+        assert!(popper.join().is_err());
+        assert_eq!(drain_touchy(&q), vec![9, 8, 7, 6, 5, 4, 3, 2, 1]);
+
+        let consumer = {
+            let q = Arc::clone(&q);
+            thread::spawn(move || q.pop().map(|t| t.value))
+        };
+        thread::sleep(Duration::from_millis(100));
+        q.push(Touchy { value: 6, armed }).unwrap();
+        assert_eq!(consumer.join().unwrap(), Some(6));
+    }
+
+    #[test]
+    fn it_should_stay_usable_after_panic_in_cmp_during_remove() {
+        let armed = Arc::new(AtomicUsize::new(0));
+        let q = Arc::new(PriorityBlockingQueue::new(10));
+        let handles: Vec<_> = [7, 3, 6, 1, 2, 5, 4]
+            .iter()
+            .map(|&value| q.push_with_handle(Touchy { value, armed: Arc::clone(&armed) }).unwrap())
+            .collect();
+        armed.store(1, AtomicOrdering::SeqCst);
+        let remover = {
+            let (q, handle) = (Arc::clone(&q), handles[1]);
+            thread::spawn(move || q.remove(handle).map(|t| t.value))
+        };
+        assert!(remover.join().is_err());
+        assert!(sync::lock(&q.state).elements.is_dirty());
+        assert!(handles.iter().all(|&handle| q.contains(handle)));
+        assert_eq!(q.remove(handles[5]).map(|t| t.value), Some(5));
+        assert_eq!(drain_touchy(&q), vec![7, 6, 4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn it_should_keep_popped_elements_when_pop_batch_panics() {
+        let (q, armed) = touchy_queue(&[8, 3, 9, 1, 6, 2, 7, 4, 5]);
+        // Late enough for a few pops to have completed.
+        armed.store(12, AtomicOrdering::SeqCst);
+        let popper = {
+            let q = Arc::clone(&q);
+            thread::spawn(move || q.pop_batch(9).len())
+        };
+        assert!(popper.join().is_err());
+        assert!(sync::lock(&q.state).elements.is_dirty());
+        assert_eq!(drain_touchy(&q), vec![9, 8, 7, 6, 5, 4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn it_should_keep_drained_elements_when_drain_sorted_panics() {
+        let (q, armed) = touchy_queue(&[8, 3, 9, 1, 6, 2, 7, 4, 5]);
+        armed.store(12, AtomicOrdering::SeqCst);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| q.drain_sorted().len()));
+        assert!(result.is_err());
+        assert_eq!(drain_touchy(&q), vec![9, 8, 7, 6, 5, 4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn it_should_stay_usable_after_panic_in_cmp_during_eviction() {
+        let q = PriorityBlockingQueue::new(5).with_overflow(Overflow::EvictLowest);
+        let (q, armed) = fill_touchy(q, &[3, 1, 4, 2, 5]);
+        // The first comparison finds the lowest element, the second one moves
+        // the last element into its place.
+        armed.store(2, AtomicOrdering::SeqCst);
+        let pusher = {
+            let (q, armed) = (Arc::clone(&q), Arc::clone(&armed));
+            thread::spawn(move || q.push_evicting(Touchy { value: 6, armed }).map(|evicted| evicted.len()))
+        };
+        assert!(pusher.join().is_err());
+        assert!(sync::lock(&q.state).elements.is_dirty());
+        assert_eq!(q.len(), 5);
+        let evicted = q.push_evicting(Touchy { value: 6, armed }).unwrap();
+        assert_eq!(evicted.iter().map(|t| t.value).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(drain_touchy(&q), vec![6, 5, 4, 3, 2]);
+    }
+
+    #[test]
+    fn it_should_keep_elements_and_handles_when_retain_panics() {
+        let q = PriorityBlockingQueue::new(10);
+        let handles: Vec<_> = (1..=4).map(|t| q.push_with_handle(t).unwrap()).collect();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            q.retain(|&t| if t == 3 { panic!("predicate failed") } else { t % 2 == 0 })
+        }));
+        assert!(result.is_err());
+        assert!(handles.iter().all(|&handle| q.contains(handle)));
+        q.remove(handles[3]).unwrap();
+        assert_eq!(q.drain_sorted(), vec![3, 2, 1]);
     }
 }
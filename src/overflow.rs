@@ -0,0 +1,164 @@
+use std::fmt;
+
+use crate::{PriorityBlockingQueue, PushError};
+
+type EvictFn<T> = dyn Fn(T) + Send + Sync;
+
+/// What `push` and `push_with_handle` do when the queue is at capacity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Refuse the new element with `PushError::Full`. The default.
+    Reject,
+    /// Evict the lowest-priority element to make room, even if the new one
+    /// ranks lower still.
+    EvictLowest,
+    /// Evict the lowest-priority element only if the new one outranks it.
+    /// Otherwise the new element is the one evicted.
+    EvictIfOutranked,
+    /// Block until there is free capacity, like `put`.
+    Block,
+}
+
+/// Receives the elements evicted by `push` and `push_with_handle`.
+pub(crate) struct OnEvict<T>(Box<EvictFn<T>>);
+
+impl<T> PriorityBlockingQueue<T> {
+    /// Sets what `push` and `push_with_handle` do when the queue is full.
+    /// `try_push`, `put` and `push_all` never evict and are unaffected.
+    ///
+    /// Evicting an element costs O(log n), like popping one.
+    pub fn with_overflow(mut self, overflow: Overflow) -> PriorityBlockingQueue<T> {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Hands elements evicted by `push` and `push_with_handle` to `on_evict`
+    /// instead of dropping them. It is called after the lock is released.
+    pub fn with_on_evict<F>(mut self, on_evict: F) -> PriorityBlockingQueue<T>
+    where
+        F: Fn(T) + Send + Sync + 'static,
+    {
+        self.on_evict = Some(OnEvict(Box::new(on_evict)));
+        self
+    }
+
+    /// Like `push`, but returns the evicted elements to the caller instead of
+    /// handing them to the eviction callback. With a weigher, one push may
+    /// evict several elements.
+    pub fn push_evicting(&self, t: T) -> Result<Vec<T>, PushError<T>> {
+        self.push_checked(t, false).map(|(_, evicted)| evicted)
+    }
+
+    pub(crate) fn hand_off_evicted(&self, evicted: Vec<T>) {
+        if let Some(on_evict) = &self.on_evict {
+            evicted.into_iter().for_each(|t| (on_evict.0)(t));
+        }
+    }
+}
+
+impl<T> fmt::Debug for OnEvict<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("OnEvict")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::{Overflow, PriorityBlockingQueue, PushError};
+
+    #[test]
+    fn it_should_evict_lowest_element_when_full() {
+        let q = PriorityBlockingQueue::new(3).with_overflow(Overflow::EvictLowest);
+        assert!(q.push_all(vec![5, 1, 3]).is_empty());
+        assert_eq!(q.push_evicting(4), Ok(vec![1]));
+        assert_eq!(q.push_evicting(0), Ok(vec![3]));
+        assert_eq!(q.drain_sorted(), vec![5, 4, 0]);
+        assert_eq!(q.push_evicting(2), Ok(vec![]));
+    }
+
+    #[test]
+    fn it_should_evict_new_element_unless_it_outranks_lowest() {
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        let q = {
+            let evicted = Arc::clone(&evicted);
+            PriorityBlockingQueue::new(2)
+                .with_overflow(Overflow::EvictIfOutranked)
+                .with_on_evict(move |t| evicted.lock().unwrap().push(t))
+        };
+        q.push(2).unwrap();
+        q.push(4).unwrap();
+        q.push(1).unwrap();
+        q.push(2).unwrap();
+        let handle = q.push_with_handle(3).unwrap();
+        assert!(q.contains(handle));
+        assert_eq!(*evicted.lock().unwrap(), vec![1, 2, 2]);
+        assert_eq!(q.drain_sorted(), vec![4, 3]);
+    }
+
+    #[test]
+    fn it_should_evict_by_weight() {
+        let q = PriorityBlockingQueue::new(10)
+            .with_weigher(10, |t: &usize| *t)
+            .with_overflow(Overflow::EvictLowest);
+        assert!(q.push_all(vec![1, 2, 3]).is_empty());
+        assert_eq!(q.push_evicting(7), Ok(vec![1, 2]));
+
+        let q = PriorityBlockingQueue::new(10)
+            .with_weigher(10, |t: &usize| *t)
+            .with_overflow(Overflow::EvictIfOutranked);
+        assert!(q.push_all(vec![3, 7]).is_empty());
+        assert_eq!(q.push_evicting(2), Ok(vec![2]));
+        assert_eq!(q.push_evicting(5), Ok(vec![5]));
+        assert_eq!(q.push_evicting(8), Ok(vec![3, 7]));
+        assert_eq!(q.drain_sorted(), vec![8]);
+    }
+
+    #[test]
+    fn it_should_not_evict_when_no_capacity_is_left() {
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        let q = {
+            let evicted = Arc::clone(&evicted);
+            PriorityBlockingQueue::new(5)
+                .with_overflow(Overflow::EvictLowest)
+                .with_on_evict(move |t| evicted.lock().unwrap().push(t))
+        };
+        assert!(q.push_all(vec![1, 2, 3]).is_empty());
+        q.set_capacity(0);
+        assert_eq!(q.push(9), Err(PushError::Full(9)));
+        assert_eq!(q.len(), 3);
+        assert!(evicted.lock().unwrap().is_empty());
+        assert_eq!(q.drain_sorted(), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn it_should_drain_gradually_below_shrunk_capacity_before_evicting() {
+        let q = PriorityBlockingQueue::new(1000).with_overflow(Overflow::EvictLowest);
+        assert!(q.push_all(0..1000).is_empty());
+        q.set_capacity(10);
+        assert_eq!(q.push_evicting(1000), Err(PushError::Full(1000)));
+        assert_eq!(q.len(), 1000);
+        assert_eq!(q.pop_batch(990).len(), 990);
+        assert_eq!(q.push_evicting(1000), Ok(vec![0]));
+        assert_eq!(q.len(), 10);
+    }
+
+    #[test]
+    fn it_should_block_push_until_capacity_frees_up() {
+        let q = Arc::new(PriorityBlockingQueue::new(1).with_overflow(Overflow::Block));
+        q.push(1).unwrap();
+        let producer = {
+            let q = Arc::clone(&q);
+            thread::spawn(move || (q.push(2), q.push(3)))
+        };
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(q.pop(), Some(1));
+        thread::sleep(Duration::from_millis(100));
+        q.close();
+        assert_eq!(producer.join().unwrap(), (Ok(()), Err(PushError::Closed(3))));
+        assert_eq!(q.pop(), Some(2));
+    }
+}
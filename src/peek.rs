@@ -0,0 +1,145 @@
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+use crate::sync::MutexGuard;
+use crate::{PriorityBlockingQueue, State};
+
+/// Mutable access to the greatest element of a queue, returned by
+/// `PriorityBlockingQueue::peek_mut`.
+///
+/// The queue stays locked while this guard is alive. If the element was
+/// changed, it is moved to its new place in priority order on drop, like
+/// `std::collections::binary_heap::PeekMut`, and blocked producers are woken if
+/// it got lighter.
+pub struct PeekMut<'a, T> {
+    queue: &'a PriorityBlockingQueue<T>,
+    state: Option<MutexGuard<'a, State<T>>>,
+    changed: bool,
+}
+
+impl<T> PriorityBlockingQueue<T> {
+    /// Calls `f` with the greatest element, if any, while holding the lock, so
+    /// no reference to it escapes.
+    pub fn peek_with<R, F: FnOnce(&T) -> R>(&self, f: F) -> Option<R> {
+        let mut state = self.lock();
+        state.elements.peek().map(f)
+    }
+
+    /// Returns a guard for changing the greatest element in place, or `None` if
+    /// the queue is empty.
+    pub fn peek_mut(&self) -> Option<PeekMut<'_, T>> {
+        let mut state = self.lock();
+        state.elements.peek()?;
+        Some(PeekMut {
+            queue: self,
+            state: Some(state),
+            changed: false,
+        })
+    }
+
+    /// Pops the greatest element only if `predicate` holds for it, atomically.
+    pub fn pop_if<F: FnOnce(&T) -> bool>(&self, predicate: F) -> Option<T> {
+        let mut state = self.lock();
+        if !state.elements.peek().is_some_and(predicate) {
+            return None;
+        }
+        Some(self.pop_head_locked(state))
+    }
+
+    /// Pops exactly the element `head` shows, even if aging keys went stale since.
+    fn pop_head_locked(&self, mut state: MutexGuard<State<T>>) -> T {
+        let (t, enqueued) = state.elements.pop_head().expect("head checked to be present");
+        Self::record_pop(&mut state, enqueued);
+        self.notify_waiters_for_pop(state, 1);
+        t
+    }
+}
+
+impl<'a, T> PeekMut<'a, T> {
+    /// Removes the peeked element from the queue and returns it.
+    pub fn pop(mut this: PeekMut<'a, T>) -> T {
+        let state = this.state.take().expect("state is only taken on pop");
+        this.queue.pop_head_locked(state)
+    }
+
+    fn state(&self) -> &State<T> {
+        self.state.as_ref().expect("state is only taken on pop")
+    }
+}
+
+impl<T> Deref for PeekMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.state().elements.head().expect("queue checked to be non-empty")
+    }
+}
+
+impl<T> DerefMut for PeekMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.changed = true;
+        let state = self.state.as_mut().expect("state is only taken on pop");
+        state.elements.head_mut().expect("queue checked to be non-empty")
+    }
+}
+
+impl<T> Drop for PeekMut<'_, T> {
+    fn drop(&mut self) {
+        if let Some(mut state) = self.state.take() {
+            if self.changed {
+                let weight = state.elements.weight();
+                state.elements.fix_head();
+                if state.elements.weight() < weight {
+                    self.queue.notify_waiters_for_pop(state, 1);
+                }
+            }
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for PeekMut<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("PeekMut").field(&**self).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{PeekMut, PriorityBlockingQueue};
+
+    #[test]
+    fn it_should_peek_without_removing() {
+        let q = PriorityBlockingQueue::new(10);
+        assert_eq!(q.peek_with(|head: &i32| *head), None);
+        assert!(q.push_all(vec![1, 3, 2]).is_empty());
+        assert_eq!(q.peek_with(|head| *head * 10), Some(30));
+        assert_eq!(q.len(), 3);
+    }
+
+    #[test]
+    fn it_should_resift_after_peek_mut_changes_head() {
+        let q = PriorityBlockingQueue::new(10);
+        assert!(q.peek_mut().is_none());
+        assert!(q.push_all(vec![1, 3, 2]).is_empty());
+        {
+            let mut head = q.peek_mut().unwrap();
+            assert_eq!(*head, 3);
+            *head = 0;
+        }
+        assert_eq!(q.drain_sorted(), vec![2, 1, 0]);
+
+        assert!(q.push_all(vec![1, 3, 2]).is_empty());
+        assert_eq!(PeekMut::pop(q.peek_mut().unwrap()), 3);
+        assert_eq!(q.len(), 2);
+    }
+
+    #[test]
+    fn it_should_pop_only_when_head_matches() {
+        let q = PriorityBlockingQueue::new(10);
+        assert!(q.push_all(vec![1, 5]).is_empty());
+        assert_eq!(q.pop_if(|head| *head > 10), None);
+        assert_eq!(q.pop_if(|head| *head > 3), Some(5));
+        assert_eq!(q.pop_if(|head| *head > 3), None);
+        assert_eq!(q.len(), 1);
+    }
+}
@@ -0,0 +1,102 @@
+use std::cmp::Ordering;
+
+use crate::{Handle, PriorityBlockingQueue, PushError};
+
+/// A value paired with the priority it is queued under.
+///
+/// Comparisons look at `priority` only, so `V` needs no ordering traits.
+#[derive(Debug, Clone, Copy)]
+pub struct Prioritized<V, P> {
+    pub value: V,
+    pub priority: P,
+}
+
+impl<V, P> Prioritized<V, P> {
+    pub fn new(value: V, priority: P) -> Prioritized<V, P> {
+        Prioritized { value, priority }
+    }
+
+    pub fn into_parts(self) -> (V, P) {
+        (self.value, self.priority)
+    }
+}
+
+impl<V, P: PartialEq> PartialEq for Prioritized<V, P> {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority == other.priority
+    }
+}
+
+impl<V, P: Eq> Eq for Prioritized<V, P> {}
+
+impl<V, P: PartialOrd> PartialOrd for Prioritized<V, P> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.priority.partial_cmp(&other.priority)
+    }
+}
+
+impl<V, P: Ord> Ord for Prioritized<V, P> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority.cmp(&other.priority)
+    }
+}
+
+impl<V, P> PriorityBlockingQueue<Prioritized<V, P>> {
+    pub fn push_with_priority(&self, value: V, priority: P) -> Result<(), PushError<Prioritized<V, P>>> {
+        self.push(Prioritized::new(value, priority))
+    }
+
+    /// Moves the element behind `handle` to `priority`.
+    /// Returns `false` if the element is no longer queued.
+    pub fn change_priority(&self, handle: Handle, priority: P) -> bool {
+        self.update(handle, |prioritized| prioritized.priority = priority)
+    }
+
+    /// Like `pop`, but splits the popped element into its value and priority.
+    pub fn pop_with_priority(&self) -> Option<(V, P)> {
+        self.pop().map(Prioritized::into_parts)
+    }
+
+    /// Like `pop`, but discards the priority.
+    pub fn pop_value(&self) -> Option<V> {
+        self.pop().map(|prioritized| prioritized.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Prioritized, PriorityBlockingQueue};
+
+    #[derive(Debug, PartialEq)]
+    struct Payload(&'static str);
+
+    #[test]
+    fn it_should_order_by_priority_only() {
+        let q = PriorityBlockingQueue::new(10);
+        q.push_with_priority(Payload("low"), 1).unwrap();
+        q.push_with_priority(Payload("high"), 3).unwrap();
+        q.push_with_priority(Payload("mid"), 2).unwrap();
+        assert_eq!(q.pop_with_priority(), Some((Payload("high"), 3)));
+        assert_eq!(q.pop_value(), Some(Payload("mid")));
+        assert_eq!(q.pop().map(|p| p.into_parts()), Some((Payload("low"), 1)));
+    }
+
+    #[test]
+    fn it_should_change_priority_by_handle() {
+        let q = PriorityBlockingQueue::new(10);
+        q.push_with_priority("routine", 5).unwrap();
+        let urgent = q.push_with_handle(Prioritized::new("urgent", 1)).unwrap();
+        assert!(q.change_priority(urgent, 10));
+        assert_eq!(q.pop_value(), Some("urgent"));
+        assert!(!q.change_priority(urgent, 0));
+        assert_eq!(q.pop_value(), Some("routine"));
+    }
+
+    #[test]
+    fn it_should_support_min_priority_mode() {
+        let q = PriorityBlockingQueue::new_min(10);
+        q.push_with_priority("later", 20).unwrap();
+        q.push_with_priority("sooner", 10).unwrap();
+        assert_eq!(q.pop_value(), Some("sooner"));
+    }
+}
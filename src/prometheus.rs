@@ -0,0 +1,88 @@
+use std::fmt::Write;
+
+use crate::stats::Histogram;
+use crate::Stats;
+
+/// Name, type, help text and value of a metric with one sample per queue.
+type Metric = (&'static str, &'static str, &'static str, fn(&Stats) -> u64);
+/// Name, help text and source of a histogram metric.
+type HistogramMetric = (&'static str, &'static str, fn(&Stats) -> &Histogram);
+
+const METRICS: [Metric; 8] = [
+    ("length", "gauge", "Elements queued right now.", |stats| stats.len as u64),
+    ("high_water_mark", "gauge", "Most elements ever queued at once.", |stats| stats.high_water_mark as u64),
+    ("pushed_total", "counter", "Elements pushed.", |stats| stats.pushed),
+    ("popped_total", "counter", "Elements popped.", |stats| stats.popped),
+    ("rejected_total", "counter", "Pushes refused for lack of room.", |stats| stats.rejected),
+    ("evicted_total", "counter", "Elements evicted by the overflow policy.", |stats| stats.evicted),
+    ("blocked_consumers", "gauge", "Consumers waiting for an element.", |stats| stats.blocked_consumers as u64),
+    ("blocked_producers", "gauge", "Producers waiting for free capacity.", |stats| stats.blocked_producers as u64),
+];
+
+const HISTOGRAMS: [HistogramMetric; 2] = [
+    ("wait_seconds", "Time blocking pops waited for an element.", |stats| &stats.wait_time),
+    ("sojourn_seconds", "Time popped elements spent queued.", |stats| &stats.sojourn_time),
+];
+
+/// Renders the stats of one or more queues in the Prometheus text exposition
+/// format, with each queue's name as the `queue` label.
+///
+/// All queues go into one call so that every metric is described only once.
+pub fn encode_prometheus(queues: &[(&str, &Stats)]) -> String {
+    let mut out = String::new();
+    let labels: Vec<_> = queues.iter().map(|(name, _)| format!("queue=\"{}\"", escape(name))).collect();
+    for (name, kind, help, value) in METRICS.iter() {
+        describe(&mut out, name, kind, help);
+        for ((_, stats), labels) in queues.iter().zip(&labels) {
+            writeln!(out, "priority_queue_{}{{{}}} {}", name, labels, value(stats)).unwrap();
+        }
+    }
+    for (name, help, histogram) in HISTOGRAMS.iter() {
+        describe(&mut out, name, "histogram", help);
+        for ((_, stats), labels) in queues.iter().zip(&labels) {
+            let histogram = histogram(stats);
+            for (bound, count) in histogram.buckets() {
+                let le = bound.map_or_else(|| "+Inf".to_string(), |bound| bound.as_secs_f64().to_string());
+                writeln!(out, "priority_queue_{}_bucket{{{},le=\"{}\"}} {}", name, labels, le, count).unwrap();
+            }
+            writeln!(out, "priority_queue_{}_sum{{{}}} {}", name, labels, histogram.sum().as_secs_f64()).unwrap();
+            writeln!(out, "priority_queue_{}_count{{{}}} {}", name, labels, histogram.count()).unwrap();
+        }
+    }
+    out
+}
+
+fn describe(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP priority_queue_{} {}", name, help).unwrap();
+    writeln!(out, "# TYPE priority_queue_{} {}", name, kind).unwrap();
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{encode_prometheus, PriorityBlockingQueue};
+
+    #[test]
+    fn it_should_encode_stats_of_several_queues() {
+        let jobs = PriorityBlockingQueue::new(1);
+        jobs.push(1).unwrap();
+        assert!(jobs.push(2).is_err());
+        let other = PriorityBlockingQueue::<u8>::new(1);
+        assert!(other.pop_timeout(Duration::from_millis(10)).is_err());
+
+        let text = encode_prometheus(&[("jobs", &jobs.stats()), ("say \"hi\"", &other.stats())]);
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.iter().filter(|line| line.starts_with("# TYPE")).count(), 10);
+        assert!(lines.contains(&"# TYPE priority_queue_wait_seconds histogram"));
+        assert!(lines.contains(&"priority_queue_length{queue=\"jobs\"} 1"));
+        assert!(lines.contains(&"priority_queue_rejected_total{queue=\"jobs\"} 1"));
+        assert!(lines.contains(&"priority_queue_wait_seconds_bucket{queue=\"say \\\"hi\\\"\",le=\"0.005\"} 0"));
+        assert!(lines.contains(&"priority_queue_wait_seconds_bucket{queue=\"say \\\"hi\\\"\",le=\"+Inf\"} 1"));
+        assert!(lines.contains(&"priority_queue_wait_seconds_count{queue=\"jobs\"} 0"));
+    }
+}
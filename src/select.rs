@@ -0,0 +1,293 @@
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::task::Waker;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::waker::ThreadWaker;
+use crate::{PopError, PriorityBlockingQueue};
+
+/// Pops from whichever of several queues has an element first.
+///
+/// A selector registers with every queue the same way `pop_async` does, using a
+/// waker that unparks the selecting thread, so waiting costs nothing until one of
+/// the queues is pushed to.
+#[derive(Debug)]
+pub struct Selector<'a, T> {
+    queues: Vec<&'a PriorityBlockingQueue<T>>,
+    compare: Option<HeadOrder<T>>,
+    next: Cell<usize>,
+}
+
+/// How `select` compares the heads of the queues.
+#[derive(Debug)]
+enum HeadOrder<T> {
+    /// By the ordering of the first queue added.
+    Queues,
+    By(fn(&T, &T) -> Ordering),
+}
+
+impl<'a, T> Selector<'a, T> {
+    pub fn new() -> Selector<'a, T> {
+        Selector {
+            queues: Vec::new(),
+            compare: None,
+            next: Cell::new(0),
+        }
+    }
+
+    /// Adds `queue` and returns the index `select` reports for its elements.
+    /// Adding the same queue twice returns its existing index.
+    pub fn add(&mut self, queue: &'a PriorityBlockingQueue<T>) -> usize {
+        match self.queues.iter().position(|added| std::ptr::eq(*added, queue)) {
+            Some(index) => index,
+            None => {
+                self.queues.push(queue);
+                self.queues.len() - 1
+            }
+        }
+    }
+
+    /// Makes `select` compare the heads of all queues and pop the greatest one,
+    /// instead of taking the first element found.
+    ///
+    /// Heads are compared by the ordering of the first queue added, so all
+    /// queues must order their elements the same way: `new` or `new_min`,
+    /// comparator or key, and aging policy.
+    pub fn by_priority(mut self) -> Selector<'a, T> {
+        self.compare = Some(HeadOrder::Queues);
+        self
+    }
+
+    /// Like `by_priority`, but compares heads with `compare`, greatest first.
+    /// It should agree with the ordering of every queue.
+    pub fn by(mut self, compare: fn(&T, &T) -> Ordering) -> Selector<'a, T> {
+        self.compare = Some(HeadOrder::By(compare));
+        self
+    }
+
+    /// Blocks until one of the queues has an element and pops it, together with
+    /// the queue's index. Returns `None` once every queue is closed and drained.
+    pub fn select(&self) -> Option<(usize, T)> {
+        self.select_deadline(None).ok()
+    }
+
+    pub fn try_select(&self) -> Option<(usize, T)> {
+        self.select_deadline(Some(Instant::now())).ok()
+    }
+
+    /// Like `select`, but gives up with `PopError::Timeout` after `timeout`.
+    /// A timeout too large to represent as a deadline waits forever.
+    pub fn select_timeout(&self, timeout: Duration) -> Result<(usize, T), PopError> {
+        self.select_deadline(Instant::now().checked_add(timeout))
+    }
+
+    fn select_deadline(&self, deadline: Option<Instant>) -> Result<(usize, T), PopError> {
+        let waker = ThreadWaker::current();
+        let mut keys = vec![None; self.queues.len()];
+        let selected = loop {
+            if let Some(selected) = self.pick() {
+                break Ok(selected);
+            }
+            match self.register(&waker, &mut keys) {
+                Registered::Ready => continue,
+                Registered::AllClosed => break Err(PopError::Closed),
+                Registered::Waiting => {}
+            }
+            match deadline {
+                None => thread::park(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break Err(PopError::Timeout);
+                    }
+                    thread::park_timeout(deadline - now);
+                }
+            }
+        };
+        for (queue, key) in self.queues.iter().zip(keys.iter_mut()) {
+            queue.cancel_pop(key);
+        }
+        selected
+    }
+
+    fn pick(&self) -> Option<(usize, T)> {
+        match &self.compare {
+            Some(compare) => self.pick_best(compare),
+            None => self.pick_first(),
+        }
+    }
+
+    /// Tries the queues round-robin so that none of them is starved.
+    fn pick_first(&self) -> Option<(usize, T)> {
+        let len = self.queues.len();
+        let start = self.next.get();
+        (0..len).map(|offset| (start + offset) % len).find_map(|index| {
+            let popped = self.queues[index].try_pop()?;
+            self.next.set((index + 1) % len);
+            Some((index, popped))
+        })
+    }
+
+    /// Locks every queue, in address order so concurrent selectors cannot
+    /// deadlock, and pops the greatest of their heads.
+    fn pick_best(&self, compare: &HeadOrder<T>) -> Option<(usize, T)> {
+        let mut order: Vec<_> = (0..self.queues.len()).collect();
+        order.sort_by_key(|&index| self.queues[index] as *const PriorityBlockingQueue<T> as usize);
+        let mut states: Vec<_> = order
+            .into_iter()
+            .map(|index| (index, self.queues[index].lock()))
+            .collect();
+        for (_, state) in states.iter_mut() {
+            state.elements.peek();
+        }
+        let first = states.iter().position(|(index, _)| *index == 0)?;
+        let best = (0..states.len())
+            .filter(|&i| !states[i].1.elements.is_empty())
+            .max_by(|&a, &b| {
+                let (a, b) = (&states[a].1.elements, &states[b].1.elements);
+                match compare {
+                    HeadOrder::Queues => states[first].1.elements.compare_heads(a, b),
+                    HeadOrder::By(compare) => compare(a.head().expect("non-empty"), b.head().expect("non-empty")),
+                }
+            })?;
+        let (index, state) = states.swap_remove(best);
+        drop(states);
+        self.queues[index].pop_locked(state).map(|popped| (index, popped))
+    }
+
+    fn register(&self, waker: &Waker, keys: &mut [Option<u64>]) -> Registered {
+        let mut open = false;
+        for (queue, key) in self.queues.iter().zip(keys.iter_mut()) {
+            let mut state = queue.lock();
+            if !state.elements.is_empty() {
+                return Registered::Ready;
+            }
+            if !state.closed {
+                state.pop_wakers.register(key, waker);
+                open = true;
+            }
+        }
+        if open {
+            Registered::Waiting
+        } else {
+            Registered::AllClosed
+        }
+    }
+}
+
+impl<T> Default for Selector<'_, T> {
+    fn default() -> Self {
+        Selector::new()
+    }
+}
+
+enum Registered {
+    Ready,
+    Waiting,
+    AllClosed,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::{PopError, PriorityBlockingQueue, Selector};
+
+    #[test]
+    fn it_should_block_until_any_queue_has_an_element() {
+        let first = Arc::new(PriorityBlockingQueue::new(10));
+        let second = Arc::new(PriorityBlockingQueue::new(10));
+        let producer = {
+            let second = Arc::clone(&second);
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(100));
+                second.push(7).unwrap();
+            })
+        };
+        let mut selector = Selector::new();
+        selector.add(&first);
+        let second_index = selector.add(&second);
+        assert_eq!(selector.add(&second), second_index);
+        let start = Instant::now();
+        assert_eq!(selector.select(), Some((second_index, 7)));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        producer.join().unwrap();
+        assert_eq!(selector.select_timeout(Duration::from_millis(50)), Err(PopError::Timeout));
+        first.push(8).unwrap();
+        assert_eq!(selector.select_timeout(Duration::MAX), Ok((0, 8)));
+    }
+
+    #[test]
+    fn it_should_take_turns_between_non_empty_queues() {
+        let first = PriorityBlockingQueue::new(10);
+        let second = PriorityBlockingQueue::new(10);
+        for i in 0..2 {
+            first.push(i).unwrap();
+            second.push(i).unwrap();
+        }
+        let mut selector = Selector::new();
+        selector.add(&first);
+        selector.add(&second);
+        let indexes: Vec<_> = std::iter::from_fn(|| selector.try_select()).map(|(index, _)| index).collect();
+        assert_eq!(indexes, vec![0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn it_should_pop_global_best_when_comparing_heads() {
+        let first = PriorityBlockingQueue::new(10);
+        let second = PriorityBlockingQueue::new(10);
+        first.push(1).unwrap();
+        first.push(4).unwrap();
+        second.push(3).unwrap();
+        let mut selector = Selector::new().by_priority();
+        selector.add(&first);
+        selector.add(&second);
+        let popped: Vec<_> = std::iter::from_fn(|| selector.try_select()).collect();
+        assert_eq!(popped, vec![(0, 4), (1, 3), (0, 1)]);
+    }
+
+    #[test]
+    fn it_should_compare_heads_by_the_queues_ordering() {
+        let first = PriorityBlockingQueue::new_min(10);
+        let second = PriorityBlockingQueue::new_min(10);
+        first.push(1).unwrap();
+        first.push(100).unwrap();
+        second.push(50).unwrap();
+        let mut selector = Selector::new().by_priority();
+        selector.add(&first);
+        selector.add(&second);
+        let popped: Vec<_> = std::iter::from_fn(|| selector.try_select()).collect();
+        assert_eq!(popped, vec![(0, 1), (1, 50), (0, 100)]);
+
+        let first = PriorityBlockingQueue::with_key(10, |t: &(u8, &str)| t.1.len());
+        let second = PriorityBlockingQueue::with_key(10, |t: &(u8, &str)| t.1.len());
+        first.push((9, "a")).unwrap();
+        second.push((1, "abc")).unwrap();
+        let mut by_key = Selector::new().by_priority();
+        by_key.add(&first);
+        by_key.add(&second);
+        assert_eq!(by_key.try_select(), Some((1, (1, "abc"))));
+        second.push((1, "abc")).unwrap();
+        let mut by_value = Selector::new().by(|a: &(u8, &str), b| a.0.cmp(&b.0));
+        by_value.add(&first);
+        by_value.add(&second);
+        assert_eq!(by_value.try_select(), Some((0, (9, "a"))));
+    }
+
+    #[test]
+    fn it_should_return_none_once_all_queues_are_closed_and_drained() {
+        let first = PriorityBlockingQueue::new(10);
+        let second = PriorityBlockingQueue::new(10);
+        second.push(1).unwrap();
+        first.close();
+        second.close();
+        let mut selector = Selector::new();
+        selector.add(&first);
+        selector.add(&second);
+        assert_eq!(selector.select(), Some((1, 1)));
+        assert_eq!(selector.select(), None);
+    }
+}
@@ -0,0 +1,103 @@
+use std::time::Duration;
+
+/// Upper bounds of the histogram buckets. Durations above the last one fall
+/// into a final, unbounded bucket.
+const BUCKETS: [Duration; 10] = [
+    Duration::from_micros(100),
+    Duration::from_millis(1),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_secs(5),
+    Duration::from_secs(10),
+];
+
+/// Point-in-time counters for a queue, as returned by `PriorityBlockingQueue::stats`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    /// Elements queued right now.
+    pub len: usize,
+    /// Most elements ever queued at once.
+    pub high_water_mark: usize,
+    /// Elements pushed in total.
+    pub pushed: u64,
+    /// Elements handed out by the pop methods in total.
+    pub popped: u64,
+    /// Pushes refused for lack of room: full, too heavy or timed out.
+    pub rejected: u64,
+    /// Elements evicted by the overflow policy in total.
+    pub evicted: u64,
+    /// Threads blocked in a pop plus futures and streams waiting to pop, right now.
+    pub blocked_consumers: usize,
+    /// Threads blocked in a push plus futures and sinks waiting to push, right now.
+    pub blocked_producers: usize,
+    /// Longest time a popped element spent queued.
+    pub max_wait: Duration,
+    /// How long blocking pops waited for an element, per wait.
+    pub wait_time: Histogram,
+    /// How long popped elements spent queued.
+    pub sojourn_time: Histogram,
+}
+
+/// Distribution of durations over fixed buckets from 100µs to 10s.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Histogram {
+    counts: [u64; BUCKETS.len() + 1],
+    sum: Duration,
+}
+
+impl Histogram {
+    pub(crate) fn observe(&mut self, duration: Duration) {
+        let bucket = BUCKETS.iter().position(|&bound| duration <= bound).unwrap_or(BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.sum += duration;
+    }
+
+    /// Number of observed durations.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Sum of the observed durations.
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    /// Upper bound of each bucket, `None` for the unbounded last one, with the
+    /// number of durations up to it. Counts are cumulative, like in Prometheus.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        let bounds = BUCKETS.iter().copied().map(Some).chain(Some(None));
+        bounds.zip(self.counts.iter().scan(0, |total, &count| {
+            *total += count;
+            Some(*total)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::stats::Histogram;
+
+    #[test]
+    fn it_should_count_durations_into_cumulative_buckets() {
+        let mut histogram = Histogram::default();
+        histogram.observe(Duration::from_micros(50));
+        histogram.observe(Duration::from_millis(1));
+        histogram.observe(Duration::from_millis(7));
+        histogram.observe(Duration::from_secs(60));
+        assert_eq!(histogram.count(), 4);
+        assert_eq!(histogram.sum(), Duration::from_micros(60_008_050));
+        let buckets: Vec<_> = histogram.buckets().collect();
+        assert_eq!(buckets.len(), 11);
+        assert_eq!(buckets[0], (Some(Duration::from_micros(100)), 1));
+        assert_eq!(buckets[1], (Some(Duration::from_millis(1)), 2));
+        assert_eq!(buckets[3], (Some(Duration::from_millis(10)), 3));
+        assert_eq!(buckets[9], (Some(Duration::from_secs(10)), 3));
+        assert_eq!(buckets[10], (None, 4));
+    }
+}
@@ -0,0 +1,145 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use futures_sink::Sink;
+
+use crate::{PriorityBlockingQueue, PushError};
+
+/// Consumer side of a queue as a `Stream`, yielding elements in priority order.
+/// The stream ends once the queue is closed and drained.
+#[derive(Debug)]
+#[must_use = "streams do nothing unless polled"]
+pub struct PopStream<T> {
+    queue: Arc<PriorityBlockingQueue<T>>,
+    key: Option<u64>,
+}
+
+impl<T> PopStream<T> {
+    pub fn new(queue: Arc<PriorityBlockingQueue<T>>) -> PopStream<T> {
+        PopStream { queue, key: None }
+    }
+}
+
+impl<T> Stream for PopStream<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        let this = self.get_mut();
+        this.queue.poll_pop(cx, &mut this.key)
+    }
+}
+
+impl<T> Drop for PopStream<T> {
+    fn drop(&mut self) {
+        self.queue.cancel_pop(&mut self.key);
+    }
+}
+
+/// Producer side of a queue as a `Sink`. Sending waits for free capacity and
+/// fails with `PushError::Closed` once the queue is closed, or with
+/// `PushError::TooHeavy` for an element that can never fit.
+///
+/// Closing the sink only flushes it; the queue itself stays open for other
+/// producers until `PriorityBlockingQueue::close` is called.
+#[derive(Debug)]
+#[must_use = "sinks do nothing unless polled"]
+pub struct PushSink<T> {
+    queue: Arc<PriorityBlockingQueue<T>>,
+    pending: Option<T>,
+    key: Option<u64>,
+}
+
+impl<T> PushSink<T> {
+    pub fn new(queue: Arc<PriorityBlockingQueue<T>>) -> PushSink<T> {
+        PushSink {
+            queue,
+            pending: None,
+            key: None,
+        }
+    }
+
+    fn poll_pending(&mut self, cx: &mut Context) -> Poll<Result<(), PushError<T>>> {
+        if self.pending.is_none() {
+            return Poll::Ready(Ok(()));
+        }
+        self.queue.poll_push(cx, &mut self.pending, &mut self.key)
+    }
+}
+
+// The pending element is only ever moved out, never pinned.
+impl<T> Unpin for PushSink<T> {}
+
+impl<T> Sink<T> for PushSink<T> {
+    type Error = PushError<T>;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), PushError<T>>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, t: T) -> Result<(), PushError<T>> {
+        let this = self.get_mut();
+        debug_assert!(this.pending.is_none(), "start_send called without poll_ready");
+        this.pending = Some(t);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), PushError<T>>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), PushError<T>>> {
+        self.get_mut().poll_pending(cx)
+    }
+}
+
+impl<T> Drop for PushSink<T> {
+    fn drop(&mut self) {
+        self.queue.cancel_push(&mut self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use futures::executor::block_on;
+    use futures::{stream, SinkExt, StreamExt};
+
+    use crate::{PopStream, PriorityBlockingQueue, PushError, PushSink};
+
+    #[test]
+    fn it_should_end_stream_when_queue_is_closed_and_drained() {
+        let q = Arc::new(PriorityBlockingQueue::new(10));
+        q.push(1).unwrap();
+        q.push(3).unwrap();
+        q.push(2).unwrap();
+        q.close();
+        let popped: Vec<_> = block_on(PopStream::new(q).collect());
+        assert_eq!(popped, vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn it_should_forward_stream_into_sink_with_backpressure() {
+        let q = Arc::new(PriorityBlockingQueue::new(2));
+        let consumer = {
+            let q = Arc::clone(&q);
+            thread::spawn(move || block_on(PopStream::new(q).collect::<Vec<_>>()))
+        };
+        block_on(stream::iter(0..100).map(Ok).forward(PushSink::new(Arc::clone(&q)))).unwrap();
+        q.close();
+        let mut popped = consumer.join().unwrap();
+        popped.sort_unstable();
+        assert_eq!(popped, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn it_should_fail_to_send_into_closed_queue() {
+        let q = Arc::new(PriorityBlockingQueue::new(10));
+        q.close();
+        let mut sink = PushSink::new(q);
+        assert_eq!(block_on(sink.send(1)), Err(PushError::Closed(1)));
+    }
+}
@@ -0,0 +1,67 @@
+//! The lock backing a queue: `std::sync` by default, `parking_lot` with the
+//! `parking_lot` feature.
+//!
+//! Poisoning is ignored either way. The queue repairs its heap after a panic
+//! itself, see `PriorityBlockingQueue::lock`.
+
+use std::time::Duration;
+
+#[cfg(not(feature = "parking_lot"))]
+pub(crate) use std::sync::{Condvar, Mutex, MutexGuard};
+
+#[cfg(feature = "parking_lot")]
+pub(crate) use parking_lot::{Condvar, Mutex, MutexGuard};
+
+#[cfg(not(feature = "parking_lot"))]
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+#[cfg(feature = "parking_lot")]
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock()
+}
+
+#[cfg(not(feature = "parking_lot"))]
+pub(crate) fn get_mut<T>(mutex: &mut Mutex<T>) -> &mut T {
+    mutex.get_mut().unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+#[cfg(feature = "parking_lot")]
+pub(crate) fn get_mut<T>(mutex: &mut Mutex<T>) -> &mut T {
+    mutex.get_mut()
+}
+
+#[cfg(not(feature = "parking_lot"))]
+pub(crate) fn into_inner<T>(mutex: Mutex<T>) -> T {
+    mutex.into_inner().unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+#[cfg(feature = "parking_lot")]
+pub(crate) fn into_inner<T>(mutex: Mutex<T>) -> T {
+    mutex.into_inner()
+}
+
+/// Waits on `condvar` until notified or, if given, `timeout` has passed.
+#[cfg(not(feature = "parking_lot"))]
+pub(crate) fn wait<'a, T>(condvar: &Condvar, guard: MutexGuard<'a, T>, timeout: Option<Duration>) -> MutexGuard<'a, T> {
+    match timeout {
+        None => condvar.wait(guard).unwrap_or_else(std::sync::PoisonError::into_inner),
+        Some(timeout) => match condvar.wait_timeout(guard, timeout) {
+            Ok((guard, _)) => guard,
+            Err(poisoned) => poisoned.into_inner().0,
+        },
+    }
+}
+
+/// Waits on `condvar` until notified or, if given, `timeout` has passed.
+#[cfg(feature = "parking_lot")]
+pub(crate) fn wait<'a, T>(condvar: &Condvar, mut guard: MutexGuard<'a, T>, timeout: Option<Duration>) -> MutexGuard<'a, T> {
+    match timeout {
+        None => condvar.wait(&mut guard),
+        Some(timeout) => {
+            condvar.wait_for(&mut guard, timeout);
+        }
+    }
+    guard
+}
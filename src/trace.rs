@@ -0,0 +1,99 @@
+//! Instrumentation that compiles to nothing unless the `tracing` feature is on.
+
+#[cfg(feature = "tracing")]
+macro_rules! event {
+    ($level:ident, $($field:tt)+) => {
+        tracing::$level!($($field)+)
+    };
+}
+
+// Fields are dropped unexpanded, so they may refer to values that only exist
+// with the feature on.
+#[cfg(not(feature = "tracing"))]
+macro_rules! event {
+    ($level:ident, $($field:tt)+) => {};
+}
+
+pub(crate) use event;
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::fmt;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    use crate::PriorityBlockingQueue;
+
+    /// Keeps the `queue` and `message` fields of every event.
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    #[derive(Default)]
+    struct Fields {
+        queue: String,
+        message: String,
+    }
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            match field.name() {
+                "message" => self.message = format!("{:?}", value),
+                "queue" => self.queue = format!("{:?}", value),
+                _ => {}
+            }
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.record_debug(field, &format_args!("{}", value));
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn new_span(&self, _: &Attributes) -> Id {
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _: &Id, _: &Record) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event) {
+            let mut fields = Fields::default();
+            event.record(&mut fields);
+            self.0.lock().unwrap().push(format!("{}: {}", fields.queue, fields.message));
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn it_should_emit_events_tagged_with_queue_name() {
+        let recorder = Arc::new(Recorder::default());
+        tracing::subscriber::with_default(Arc::clone(&recorder), || {
+            let q = PriorityBlockingQueue::new(1).with_name("jobs");
+            q.push(1).unwrap();
+            assert!(q.push(2).is_err());
+            assert_eq!(q.pop(), Some(1));
+            assert!(q.pop_timeout(Duration::from_millis(10)).is_err());
+        });
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            vec![
+                "jobs: pushed",
+                "jobs: rejected push",
+                "jobs: popped",
+                "jobs: wait timed out",
+            ]
+        );
+    }
+}
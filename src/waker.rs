@@ -0,0 +1,64 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::task::{Wake, Waker};
+use std::thread::{self, Thread};
+
+/// Wakers of futures waiting on one side of a queue, woken in registration order.
+///
+/// A registration is removed when its waker is taken for waking, which is how a
+/// future can tell on cancellation that it was notified and must pass that on.
+#[derive(Debug, Default)]
+pub(crate) struct WakerSet {
+    wakers: BTreeMap<u64, Waker>,
+    next_key: u64,
+}
+
+impl WakerSet {
+    /// Registers `waker` under `key`, replacing the previous waker if `key` is still registered.
+    pub(crate) fn register(&mut self, key: &mut Option<u64>, waker: &Waker) {
+        if let Some(registered) = key.and_then(|key| self.wakers.get_mut(&key)) {
+            if !registered.will_wake(waker) {
+                *registered = waker.clone();
+            }
+            return;
+        }
+        let new_key = self.next_key;
+        self.next_key += 1;
+        self.wakers.insert(new_key, waker.clone());
+        *key = Some(new_key);
+    }
+
+    /// Removes the registration under `key`. Returns `false` if it had already been taken.
+    pub(crate) fn remove(&mut self, key: u64) -> bool {
+        self.wakers.remove(&key).is_some()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.wakers.len()
+    }
+
+    /// Takes up to `count` wakers, oldest registrations first.
+    pub(crate) fn take(&mut self, count: usize) -> Vec<Waker> {
+        let keys: Vec<_> = self.wakers.keys().take(count).copied().collect();
+        keys.iter().filter_map(|key| self.wakers.remove(key)).collect()
+    }
+
+    pub(crate) fn take_all(&mut self) -> Vec<Waker> {
+        std::mem::take(&mut self.wakers).into_values().collect()
+    }
+}
+
+/// Waker that unparks the thread that created it, for blocking on several queues at once.
+pub(crate) struct ThreadWaker(Thread);
+
+impl ThreadWaker {
+    pub(crate) fn current() -> Waker {
+        Waker::from(Arc::new(ThreadWaker(thread::current())))
+    }
+}
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}